@@ -1,11 +1,41 @@
-use std::path::PathBuf;
-use std::{fs::File, io};
-use web_audio_api::context::{AudioContext, BaseAudioContext};
+use std::fs;
+use std::io;
+use std::mem;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use web_audio_api::context::{
+    AudioContext, AudioContextLatencyCategory, AudioContextOptions, AudioContextState,
+    BaseAudioContext,
+};
 use web_audio_api::node::{
-    AudioBufferSourceNode, AudioNode, AudioScheduledSourceNode, BiquadFilterNode, GainNode,
+    AudioBufferSourceNode, AudioNode, AudioScheduledSourceNode, BiquadFilterNode, BiquadFilterType,
+    GainNode, StereoPannerNode,
 };
+use web_audio_api::{enumerate_devices, AudioBuffer, MediaDeviceInfoKind};
+
+use crate::buffer_cache::{BufferCache, SampleBuffers};
+use crate::effects::{DelayBus, ReverbBus};
+use crate::granular::Granulator;
+use crate::lfo::Lfo;
+use crate::limiter::Limiter;
+use crate::message::ControlMessage;
+use crate::meter::{MeterTaps, Metering, Tap};
+use crate::recorder::{self, Recorder};
+use crate::sampler::{self, Sampler};
 
-use crate::settings::Settings;
+use crate::settings::{
+    AudioSettings, ChannelEngine, ChannelSettings, FilterType, LatencyCategory, LatencyHint,
+    LfoTarget, PlaybackMode, Settings,
+};
+use crate::streaming::StreamingSource;
+use crate::stretch::Stretcher;
+use crate::watchdog::{spawn_watchdog, Watchdog};
+use crate::wav;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -13,71 +43,793 @@ pub enum Error {
     OpenFile(#[from] io::Error),
     #[error("failed to decode audio")]
     DecodeAudio(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("failed to stream audio file: {0}")]
+    StreamAudio(String),
+    #[error("audio output device {0:?} not found")]
+    MissingSink(String),
+    #[error("no audio output device available")]
+    NoOutputDevice,
+    #[error("failed to record audio input")]
+    Sampler(#[from] sampler::Error),
+    #[error("failed to record master output")]
+    Recorder(#[from] recorder::Error),
+}
+
+/// Number of points used to describe the equal-power crossfade curves
+const CROSSFADE_CURVE_LENGTH: usize = 64;
+/// Seconds taken to sweep the filter back to its frequency after changing filter type
+const FILTER_TYPE_RAMP_TIME: f64 = 0.05;
+/// Seconds taken to glide to a new filter frequency or q, control values arrive in steps that
+/// would otherwise be heard, most of all through the peaking boost
+const FILTER_PARAM_RAMP_TIME: f64 = 0.02;
+/// Boost in dB applied around the filter frequency in peaking mode
+const PEAKING_GAIN: f32 = 12.0;
+/// Sink id that renders the graph without playing it through an output device
+const NO_SINK_ID: &str = "none";
+/// Shortest loop as a fraction of the sample, a zero length loop would play the whole sample
+const MIN_LOOP_LENGTH: f64 = 0.001;
+/// Seconds taken to fade between one shots, short enough to keep retriggers tight
+const ONE_SHOT_FADE_TIME: f64 = 0.005;
+const DEFAULT_FILTER_FREQUENCY: f32 = 1800.0;
+const MIN_FILTER_FREQUENCY: f32 = 20.0;
+const MAX_FILTER_FREQUENCY: f32 = 20000.0;
+/// Seconds after a stream's fade out that it is stopped, leaving room for the audio clock to
+/// catch up with the wall clock
+const STREAM_STOP_DELAY: f64 = 0.1;
+
+impl From<LatencyHint> for AudioContextLatencyCategory {
+    fn from(latency_hint: LatencyHint) -> Self {
+        match latency_hint {
+            LatencyHint::Category(LatencyCategory::Balanced) => Self::Balanced,
+            LatencyHint::Category(LatencyCategory::Interactive) => Self::Interactive,
+            LatencyHint::Category(LatencyCategory::Playback) => Self::Playback,
+            LatencyHint::Seconds(latency) => Self::Custom(latency),
+        }
+    }
+}
+
+/// Sink id and label of every audio output device
+pub fn output_devices() -> Vec<(String, String)> {
+    enumerate_devices()
+        .into_iter()
+        .filter(|device| device.kind() == MediaDeviceInfoKind::AudioOutput)
+        .map(|device| (device.device_id().to_string(), device.label().to_string()))
+        .collect()
+}
+
+/// The audio context panics on an unknown sink id or when there is no output device at all, so
+/// check the sink id against the output devices first
+fn context_options(settings: &AudioSettings) -> Result<AudioContextOptions, Error> {
+    let sink_id = settings.sink_id();
+    if sink_id != NO_SINK_ID {
+        let devices = output_devices();
+        if sink_id.is_empty() && devices.is_empty() {
+            return Err(Error::NoOutputDevice);
+        }
+        if !sink_id.is_empty() && !devices.iter().any(|(id, _)| id == sink_id) {
+            return Err(Error::MissingSink(sink_id.into()));
+        }
+    }
+
+    Ok(AudioContextOptions {
+        sink_id: sink_id.into(),
+        ..render_only_options(settings)
+    })
+}
+
+/// Options for a context that renders the graph without playing it through an output device
+fn render_only_options(settings: &AudioSettings) -> AudioContextOptions {
+    AudioContextOptions {
+        latency_hint: settings.latency_hint().into(),
+        sample_rate: settings.sample_rate(),
+        sink_id: NO_SINK_ID.into(),
+        ..AudioContextOptions::default()
+    }
+}
+
+fn open_context(options: AudioContextOptions) -> Result<AudioContext, Error> {
+    // the device can still go away between listing it and opening it
+    panic::catch_unwind(|| AudioContext::new(options)).map_err(|_| Error::NoOutputDevice)
+}
+
+impl From<FilterType> for BiquadFilterType {
+    fn from(filter_type: FilterType) -> Self {
+        match filter_type {
+            FilterType::Lowpass => BiquadFilterType::Lowpass,
+            FilterType::Highpass => BiquadFilterType::Highpass,
+            FilterType::Bandpass => BiquadFilterType::Bandpass,
+            FilterType::Notch => BiquadFilterType::Notch,
+            FilterType::Peaking => BiquadFilterType::Peaking,
+        }
+    }
+}
+
+/// A source and its gain, one of the two voices a channel crossfades between
+struct Voice {
+    gain: GainNode,
+    source: Option<AudioBufferSourceNode>,
+    /// Plays instead of `source` when the sample is streamed from disk
+    stream: Option<StreamingSource>,
+    /// When the voice's fade out finishes, `None` while it is playing or waiting to be played
+    fade_end: Option<f64>,
+}
+
+impl Voice {
+    fn new(context: &impl BaseAudioContext, destination: &BiquadFilterNode) -> Self {
+        let gain = context.create_gain();
+        gain.gain().set_value(0.0);
+        gain.connect(destination);
+
+        Self {
+            gain,
+            source: None,
+            stream: None,
+            fade_end: None,
+        }
+    }
+
+    fn is_fading(&self, now: f64) -> bool {
+        self.fade_end.map(|end| end > now).unwrap_or(false)
+    }
+
+    /// Stop and drop whatever the voice was playing
+    fn clear(&mut self, rate_modulation: &GainNode) {
+        if let Some(previous) = self.source.take() {
+            previous.stop();
+            previous.disconnect();
+            rate_modulation.disconnect_from(previous.playback_rate());
+        }
+        if let Some(mut previous) = self.stream.take() {
+            previous.stop();
+        }
+        self.fade_end = None;
+    }
+
+    /// Clear the voice and disconnect its gain, once it is no longer needed
+    fn release(&mut self, rate_modulation: &GainNode) {
+        self.clear(rate_modulation);
+        self.gain.disconnect();
+    }
+
+    fn fade(&self, curve: Vec<f32>, now: f64, fade_time: f64) {
+        let gain = self.gain.gain();
+        gain.cancel_and_hold_at_time(now);
+
+        if fade_time > 0.0 {
+            gain.set_value_curve_at_time(&curve, now, fade_time);
+        } else if let Some(value) = curve.last() {
+            gain.set_value_at_time(*value, now);
+        }
+    }
 }
 
 pub struct AudioGraphChannel {
     filter: BiquadFilterNode,
+    panner: StereoPannerNode,
     volume: GainNode,
-    source: AudioBufferSourceNode,
+    delay_send: GainNode,
+    reverb_send: GainNode,
+    /// Sums lfos targeting the playback rate so they follow each new source
+    rate_modulation: GainNode,
+    filter_type: FilterType,
+    /// Frequency set by the filter frequency control, what filter type changes sweep back to
+    filter_frequency: f32,
+    voices: [Voice; 2],
+    active_voice: usize,
+    /// Voices that were still fading out when their slot was needed for a new source, released
+    /// once their fade has finished
+    fading: Vec<Voice>,
+    crossfade_time: f64,
+    /// Playback rate set by the rate control, carried over to each new source
+    rate: f32,
+    /// Start of the loop as a fraction of the sample length
+    loop_start: f64,
+    /// Length of the loop as a fraction of the sample length
+    loop_length: f64,
+    /// Loop start and length set by the loop controls, the loop of samples without loop points
+    /// of their own
+    control_loop: (f64, f64),
+    playback_mode: PlaybackMode,
+    /// Beats the loop is stretched to fill at `bpm`, `None` when not synced
+    sync_beats: Option<f32>,
+    bpm: f32,
+    /// Pitch shift in semitones
+    pitch: f32,
+    /// The loaded sample, new sources are created from it whenever the mode or loop changes.
+    /// Shared with the buffer cache, so a reversed copy built for it counts against the cache.
+    buffers: Option<Arc<SampleBuffers>>,
+    /// The loop stretched to the synced length and to make up for the pitch shift, new sources
+    /// are created from this instead when there is one
+    stretched: Option<SampleBuffers>,
+    stretcher: Stretcher,
+    /// Counts stretches asked for, only the result of the latest is used
+    stretch_generation: u64,
+    control_tx: Sender<ControlMessage>,
+    /// Replaces the looping voices when the channel uses the granular engine
+    granulator: Option<Granulator>,
+    /// Post fader level
+    meter: Tap,
+    /// File the playing sample was loaded from, reloaded when the audio graph is rebuilt
+    sample_path: Option<PathBuf>,
 }
 
 impl AudioGraphChannel {
-    fn new(context: &AudioContext, destination: &GainNode) -> Self {
+    fn new(
+        context: &AudioContext,
+        destination: &GainNode,
+        delay_bus: &DelayBus,
+        reverb_bus: &ReverbBus,
+        settings: &ChannelSettings,
+        bpm: f32,
+        control_tx: Sender<ControlMessage>,
+    ) -> Self {
         let volume = context.create_gain();
         volume.gain().set_value(0.0);
         volume.connect(destination);
+        let meter = Tap::new(context, &volume);
+
+        let panner = context.create_stereo_panner();
+        panner.pan().set_value(settings.pan());
+        panner.connect(&volume);
 
         let filter = context.create_biquad_filter();
-        filter.set_type(web_audio_api::node::BiquadFilterType::Bandpass);
-        filter.frequency().set_value(1800.0);
+        filter.set_type(settings.filter_type().into());
+        filter.frequency().set_value(DEFAULT_FILTER_FREQUENCY);
         filter.q().set_value(0.667);
-        filter.connect(&volume);
+        filter.gain().set_value(PEAKING_GAIN);
+        filter.connect(&panner);
 
-        let source = context.create_buffer_source();
-        source.set_loop(true);
-        source.connect(&filter);
+        let delay_send = context.create_gain();
+        delay_send.gain().set_value(0.0);
+        delay_send.connect(delay_bus.input());
+        filter.connect(&delay_send);
+
+        let reverb_send = context.create_gain();
+        reverb_send.gain().set_value(0.0);
+        reverb_send.connect(reverb_bus.input());
+        filter.connect(&reverb_send);
+
+        let rate_modulation = context.create_gain();
+
+        let voices = [Voice::new(context, &filter), Voice::new(context, &filter)];
+        let granulator = match settings.engine() {
+            ChannelEngine::Looper => None,
+            ChannelEngine::Granular => Some(Granulator::new(context, &filter)),
+        };
 
         Self {
             filter,
-            source,
+            panner,
             volume,
+            delay_send,
+            reverb_send,
+            rate_modulation,
+            filter_type: settings.filter_type(),
+            filter_frequency: DEFAULT_FILTER_FREQUENCY,
+            voices,
+            active_voice: 0,
+            fading: Vec::new(),
+            crossfade_time: settings.crossfade_time(),
+            rate: 1.0,
+            loop_start: 0.0,
+            loop_length: 1.0,
+            control_loop: (0.0, 1.0),
+            playback_mode: settings.playback_mode(),
+            sync_beats: settings.sync_beats(),
+            bpm,
+            pitch: settings.pitch(),
+            buffers: None,
+            stretched: None,
+            stretcher: Stretcher::new(control_tx.clone()),
+            control_tx,
+            stretch_generation: 0,
+            granulator,
+            meter,
+            sample_path: None,
         }
     }
 
     pub fn set_filter_q(&self, value: f32) {
-        self.filter.q().set_value(value);
+        let now = self.filter.context().current_time();
+        let q = self.filter.q();
+        q.cancel_and_hold_at_time(now);
+        q.linear_ramp_to_value_at_time(value, now + FILTER_PARAM_RAMP_TIME);
     }
 
-    pub fn set_filter_frequency(&self, value: f32) {
-        self.filter.frequency().set_value(value);
+    pub fn set_filter_frequency(&mut self, value: f32) {
+        self.filter_frequency = value.clamp(MIN_FILTER_FREQUENCY, MAX_FILTER_FREQUENCY);
+        let now = self.filter.context().current_time();
+        let frequency = self.filter.frequency();
+        frequency.cancel_and_hold_at_time(now);
+        frequency
+            .exponential_ramp_to_value_at_time(self.filter_frequency, now + FILTER_PARAM_RAMP_TIME);
+    }
+
+    pub fn set_delay_send(&self, value: f32) {
+        self.delay_send.gain().set_value(value);
+    }
+
+    pub fn set_reverb_send(&self, value: f32) {
+        self.reverb_send.gain().set_value(value);
+    }
+
+    /// Change the filter type, sweeping the frequency in from the edge of the spectrum where the
+    /// new filter is near silent or transparent so the switch doesn't click. A peaking boost
+    /// swept across the spectrum chirps, so it is faded in at the frequency instead.
+    pub fn set_filter_type(&mut self, filter_type: FilterType) {
+        // midi sends the type again for every step within its range
+        if filter_type == self.filter_type {
+            return;
+        }
+        self.filter_type = filter_type;
+        let now = self.filter.context().current_time();
+        self.filter.set_type(filter_type.into());
+
+        if filter_type == FilterType::Peaking {
+            let gain = self.filter.gain();
+            gain.cancel_scheduled_values(now);
+            gain.set_value_at_time(0.0, now);
+            gain.linear_ramp_to_value_at_time(PEAKING_GAIN, now + FILTER_TYPE_RAMP_TIME);
+            return;
+        }
+
+        let frequency = self.filter.frequency();
+        let start = match filter_type {
+            FilterType::Highpass => MAX_FILTER_FREQUENCY,
+            _ => MIN_FILTER_FREQUENCY,
+        };
+        frequency.cancel_scheduled_values(now);
+        frequency.set_value_at_time(start, now);
+        frequency
+            .exponential_ramp_to_value_at_time(self.filter_frequency, now + FILTER_TYPE_RAMP_TIME);
+    }
+
+    pub fn set_pan(&self, value: f32) {
+        self.panner.pan().set_value(value);
     }
 
     pub fn set_volume(&self, value: f32) {
         self.volume.gain().set_value(value);
     }
 
-    pub fn set_rate(&self, value: f32) {
-        // self.source.playback_rate().set_value(value);
-        todo!()
+    pub fn set_rate(&mut self, value: f32) {
+        self.rate = value;
+        if let Some(granulator) = &self.granulator {
+            granulator.set_rate(value);
+        }
+        if let Some(source) = self.source() {
+            source.playback_rate().set_value(value);
+        }
+        if let Some(stream) = &mut self.voices[self.active_voice].stream {
+            stream.set_rate(value);
+        }
     }
 
-    pub fn load(&mut self, context: &AudioContext, path: &PathBuf) -> Result<(), Error> {
-        let file = File::open(path)?;
-        let buffer = context.decode_audio_data_sync(file)?;
-        let source = context.create_buffer_source();
-        source.set_loop(true);
-        source.connect(&self.filter);
-        source.set_buffer(buffer);
+    fn set_loop_start(&mut self, value: f32) {
+        self.loop_start = (value as f64).clamp(0.0, 1.0 - MIN_LOOP_LENGTH);
+        self.control_loop.0 = self.loop_start;
+        self.update_loop();
+    }
+
+    fn set_loop_length(&mut self, value: f32) {
+        self.loop_length = (value as f64).clamp(MIN_LOOP_LENGTH, 1.0);
+        self.control_loop.1 = self.loop_length;
+        self.update_loop();
+    }
+
+    /// Go back to the loop set by the loop controls, for a sample without loop points
+    fn reset_loop(&mut self) {
+        (self.loop_start, self.loop_length) = self.control_loop;
+    }
 
-        // out with the old and in with the new
-        self.source.disconnect();
-        self.source = source;
+    fn loop_end(&self) -> f64 {
+        (self.loop_start + self.loop_length).min(1.0)
+    }
+
+    fn update_loop(&mut self) {
+        // stretched and ping pong buffers are built from the loop, so they have to be built again
+        if self.playback_mode == PlaybackMode::PingPong || self.is_stretched() {
+            if !self.update_stretch() {
+                self.restart();
+            }
+        } else if let Some(source) = self.source() {
+            self.apply_loop_points(source);
+        }
+    }
+
+    /// Loop within the buffers new sources are created from, as fractions of their length
+    fn source_loop(&self) -> (f64, f64) {
+        match self.stretched {
+            Some(_) => (0.0, 1.0),
+            None => (self.loop_start, self.loop_end()),
+        }
+    }
+
+    fn apply_loop_points(&self, source: &AudioBufferSourceNode) {
+        let Some(duration) = source.buffer().map(|buffer| buffer.duration()) else {
+            return;
+        };
+        let (loop_start, loop_end) = self.source_loop();
+        let (start, end) = match self.playback_mode {
+            PlaybackMode::ForwardLoop | PlaybackMode::OneShot => (loop_start, loop_end),
+            PlaybackMode::ReverseLoop => (1.0 - loop_end, 1.0 - loop_start),
+            PlaybackMode::PingPong => (0.0, 1.0),
+        };
+        source.set_loop_start(start * duration);
+        source.set_loop_end(end * duration);
+    }
+
+    /// Crossfade into a new source playing the sample in the new mode
+    pub fn set_playback_mode(&mut self, context: &AudioContext, playback_mode: PlaybackMode) {
+        if playback_mode == self.playback_mode {
+            return;
+        }
+        self.playback_mode = playback_mode;
+        if self.queue_source(context) {
+            self.play(context);
+        }
+    }
+
+    /// Restart the sample from the start of the loop, this is how one shots are played
+    pub fn trigger(&mut self, context: &AudioContext) {
+        if self.queue_source(context) {
+            self.play(context);
+        }
+    }
+
+    /// Shift the pitch in semitones without changing the length of the loop
+    pub fn set_pitch(&mut self, value: f32) {
+        if value == self.pitch {
+            return;
+        }
+        self.pitch = value;
+        if !self.update_stretch() {
+            self.restart();
+        }
+    }
+
+    pub fn set_bpm(&mut self, value: f32) {
+        self.bpm = value;
+        if self.sync_beats.is_some() && !self.update_stretch() {
+            self.restart();
+        }
+    }
+
+    fn is_stretched(&self) -> bool {
+        self.sync_beats.is_some() || self.pitch != 0.0
+    }
+
+    /// Stretch the loop to fill the synced beats, and by the pitch ratio so that playing it
+    /// detuned doesn't change its length. Stretching runs on the stretcher's thread and the
+    /// channel keeps playing what it was until `finish_stretch` crossfades into the result.
+    /// Returns false if nothing is stretched, for the caller to restart the source itself.
+    fn update_stretch(&mut self) -> bool {
+        self.stretch_generation += 1;
+        let buffers = match &self.buffers {
+            Some(buffers) if self.is_stretched() => buffers,
+            _ => {
+                self.stretched = None;
+                return false;
+            }
+        };
+
+        let duration = buffers.forward().duration() * (self.loop_end() - self.loop_start);
+        let target = self
+            .sync_beats
+            .map(|beats| beats as f64 * 60.0 / self.bpm as f64)
+            .unwrap_or(duration);
+        let pitch_ratio = (self.pitch as f64 / 12.0).exp2();
+        self.stretcher.stretch(
+            buffers.forward(),
+            self.loop_start,
+            self.loop_end(),
+            target / duration * pitch_ratio,
+            self.stretch_generation,
+        );
+
+        true
+    }
+
+    /// Crossfade into the loop stretched by the latest `update_stretch`, if it has finished
+    fn finish_stretch(&mut self) {
+        let Some(forward) = self.stretcher.take(self.stretch_generation) else {
+            return;
+        };
+        self.stretched = Some(SampleBuffers::new(forward));
+        self.restart();
+    }
+
+    /// Crossfade into a new source for the loaded sample
+    fn restart(&mut self) {
+        let context = self.filter.context().clone();
+        if self.queue_source(&context) {
+            self.play(&context);
+        }
+    }
+
+    /// The granular engine, if the channel uses it
+    pub fn granulator(&self) -> Option<&Granulator> {
+        self.granulator.as_ref()
+    }
+
+    /// The param, or node feeding it, that an lfo connects to for a target
+    fn modulation_input(&self, target: LfoTarget) -> &dyn AudioNode {
+        match target {
+            LfoTarget::FilterFrequency => self.filter.frequency(),
+            LfoTarget::FilterQ => self.filter.q(),
+            LfoTarget::Pan => self.panner.pan(),
+            LfoTarget::Rate => &self.rate_modulation,
+            LfoTarget::Volume => self.volume.gain(),
+        }
+    }
+
+    fn source(&self) -> Option<&AudioBufferSourceNode> {
+        self.voices[self.active_voice].source.as_ref()
+    }
+
+    /// Load a decoded sample file into the inactive voice, ready to be crossfaded in by `play`
+    pub fn load(&mut self, context: &AudioContext, path: &Path, buffers: Arc<SampleBuffers>) {
+        // loop points saved in the file replace the channel's loop until another sample is loaded
+        match wav::read_loop_points(path) {
+            Some((start, end)) => {
+                let duration = buffers.forward().duration();
+                self.loop_start = (start / duration).clamp(0.0, 1.0 - MIN_LOOP_LENGTH);
+                self.loop_length = ((end - start) / duration).clamp(MIN_LOOP_LENGTH, 1.0);
+            }
+            None => self.reset_loop(),
+        }
+
+        self.load_buffers(context, buffers);
+        self.sample_path = Some(path.to_path_buf());
+    }
+
+    /// Load decoded audio into the inactive voice, ready to be crossfaded in by `play`
+    pub fn load_buffers(&mut self, context: &AudioContext, buffers: Arc<SampleBuffers>) {
+        if let Some(granulator) = &self.granulator {
+            granulator.set_buffer(buffers.forward().clone());
+            return;
+        }
+
+        self.buffers = Some(buffers);
+        // the sample plays unstretched until its stretched loop is ready
+        self.stretched = None;
+        self.update_stretch();
+        self.queue_source(context);
+    }
+
+    /// Open a sample file to stream from disk in the inactive voice, ready to be crossfaded in by
+    /// `play`. Streams loop the whole file forwards, loop points, playback mode, sync and pitch
+    /// don't apply to them.
+    pub fn load_stream(&mut self, context: &AudioContext, path: &Path) -> Result<(), Error> {
+        let stream = StreamingSource::new(context, path, self.rate)?;
+        self.buffers = None;
+        self.update_stretch();
+        self.reset_loop();
+
+        let incoming = self.incoming_voice(context);
+        stream.node().connect(&incoming.gain);
+        incoming.stream = Some(stream);
+        self.sample_path = Some(path.to_path_buf());
 
         Ok(())
     }
 
-    pub fn play(&self) {
-        self.source.start();
+    /// Create a source for the loaded sample in the inactive voice, returns false if there is no
+    /// decoded sample to create it from
+    fn queue_source(&mut self, context: &impl BaseAudioContext) -> bool {
+        let Some(buffers) = self.stretched.as_ref().or(self.buffers.as_deref()) else {
+            return false;
+        };
+        let (loop_start, loop_end) = self.source_loop();
+
+        let source = context.create_buffer_source();
+        source.set_buffer(match self.playback_mode {
+            PlaybackMode::ForwardLoop | PlaybackMode::OneShot => buffers.forward().clone(),
+            PlaybackMode::ReverseLoop => buffers.reversed().clone(),
+            PlaybackMode::PingPong => ping_pong_buffer(buffers.forward(), loop_start, loop_end),
+        });
+        source.set_loop(self.playback_mode != PlaybackMode::OneShot);
+        source.detune().set_value(self.pitch * 100.0);
+        self.apply_loop_points(&source);
+
+        source.playback_rate().set_value(self.rate);
+
+        self.rate_modulation.connect(source.playback_rate());
+        let incoming = self.incoming_voice(context);
+        source.connect(&incoming.gain);
+        incoming.source = Some(source);
+
+        true
+    }
+
+    /// Clear the inactive voice for a new source. A voice that is still fading out is set aside
+    /// with its own gain until the fade has finished, rather than being cut short.
+    fn incoming_voice(&mut self, context: &impl BaseAudioContext) -> &mut Voice {
+        let now = context.current_time();
+        self.release_faded_voices(now);
+
+        let index = 1 - self.active_voice;
+        if self.voices[index].is_fading(now) {
+            let replacement = Voice::new(context, &self.filter);
+            self.fading
+                .push(mem::replace(&mut self.voices[index], replacement));
+        } else {
+            self.voices[index].clear(&self.rate_modulation);
+        }
+        &mut self.voices[index]
+    }
+
+    /// Start the most recently loaded sample, crossfading out whatever was playing before
+    pub fn play(&mut self, context: &impl BaseAudioContext) {
+        // grains start playing as soon as the granulator has a buffer
+        if self.granulator.is_some() {
+            return;
+        }
+
+        let now = context.current_time();
+        let outgoing_index = self.active_voice;
+        self.active_voice = 1 - self.active_voice;
+
+        let fade_time = match self.playback_mode {
+            PlaybackMode::OneShot => ONE_SHOT_FADE_TIME,
+            _ => self.crossfade_time,
+        };
+
+        let incoming = &mut self.voices[self.active_voice];
+        incoming.fade_end = None;
+        if let Some(source) = &incoming.source {
+            let offset = source.loop_start();
+            match self.playback_mode {
+                PlaybackMode::OneShot => {
+                    source.start_at_with_offset_and_duration(
+                        now,
+                        offset,
+                        source.loop_end() - offset,
+                    );
+                }
+                _ => source.start_at_with_offset(now, offset),
+            }
+        }
+        if let Some(stream) = &mut incoming.stream {
+            stream.play();
+        }
+        incoming.fade(equal_power_curve(0.0, 1.0), now, fade_time);
+
+        let outgoing = &mut self.voices[outgoing_index];
+        let level = outgoing.gain.gain().value();
+        outgoing.fade(equal_power_curve(level, 0.0), now, fade_time);
+        outgoing.fade_end = Some(now + fade_time);
+
+        // the source is dropped once its fade has finished, when this voice is next loaded or set
+        // aside if it is loaded sooner. A stream can't be stopped ahead of time, so the control
+        // thread is asked to stop it once its fade has finished rather than let it read on.
+        if let Some(source) = &outgoing.source {
+            source.stop_at(now + fade_time);
+        }
+        if outgoing.stream.is_some() {
+            let control_tx = self.control_tx.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_secs_f64(fade_time + STREAM_STOP_DELAY));
+                let _ = control_tx.send(ControlMessage::StopFadedStreams);
+            });
+        }
+    }
+
+    /// Stop the streams of voices whose fade out has finished
+    fn stop_faded_streams(&mut self) {
+        let now = self.filter.context().current_time();
+        self.release_faded_voices(now);
+
+        let outgoing = &mut self.voices[1 - self.active_voice];
+        if outgoing.stream.is_some() && outgoing.fade_end.is_some() && !outgoing.is_fading(now) {
+            outgoing.clear(&self.rate_modulation);
+        }
+    }
+
+    /// Release the voices set aside to fade out once their fade has finished
+    fn release_faded_voices(&mut self, now: f64) {
+        let rate_modulation = &self.rate_modulation;
+        self.fading.retain_mut(|voice| {
+            let finished = !voice.is_fading(now);
+            if finished {
+                voice.release(rate_modulation);
+            }
+            !finished
+        });
+    }
+}
+
+/// The loop played forwards then backwards, between fractions of the buffer
+fn ping_pong_buffer(buffer: &AudioBuffer, start: f64, end: f64) -> AudioBuffer {
+    let length = buffer.length();
+    let start_frame = ((start * length as f64) as usize).min(length - 1);
+    let end_frame = ((end * length as f64) as usize).clamp(start_frame + 1, length);
+
+    let samples = (0..buffer.number_of_channels())
+        .map(|channel| {
+            let region = &buffer.get_channel_data(channel)[start_frame..end_frame];
+            region.iter().chain(region.iter().rev()).copied().collect()
+        })
+        .collect();
+    AudioBuffer::from(samples, buffer.sample_rate())
+}
+
+/// Gain curve for an equal-power fade between two levels
+fn equal_power_curve(from: f32, to: f32) -> Vec<f32> {
+    (0..CROSSFADE_CURVE_LENGTH)
+        .map(|index| {
+            let position = index as f32 / (CROSSFADE_CURVE_LENGTH - 1) as f32;
+            let angle = position * std::f32::consts::FRAC_PI_2;
+            from * angle.cos() + to * angle.sin()
+        })
+        .collect()
+}
+
+/// Which channel the audio input is being recorded into and whether the master output is being
+/// recorded, a read only view of the audio graph that stays up to date as recordings start and stop
+#[derive(Clone, Debug, Default)]
+pub struct RecordingState {
+    channel: Arc<RwLock<Option<usize>>>,
+    /// Cleared by the recorder itself if writing to disk fails
+    master: Arc<AtomicBool>,
+}
+
+impl RecordingState {
+    pub fn channel(&self) -> Option<usize> {
+        *self.channel.read().expect("Recording state lock")
+    }
+
+    pub fn master(&self) -> bool {
+        self.master.load(Ordering::SeqCst)
+    }
+
+    fn set_channel(&self, channel: Option<usize>) {
+        *self.channel.write().expect("Recording state lock") = channel;
+    }
+}
+
+/// Loop start and length of each channel as fractions of its sample, a read only view of the
+/// audio graph that follows the loop controls and the loop points saved in sample files
+#[derive(Clone, Debug)]
+pub struct ChannelLoops {
+    loops: Arc<RwLock<Vec<(f64, f64)>>>,
+}
+
+impl ChannelLoops {
+    pub fn get(&self, channel_index: usize) -> Option<(f64, f64)> {
+        let loops = self.loops.read().expect("Channel loops lock");
+        loops.get(channel_index).copied()
+    }
+
+    fn set(&self, channel_index: usize, loop_points: (f64, f64)) {
+        let mut loops = self.loops.write().expect("Channel loops lock");
+        if let Some(channel_loop) = loops.get_mut(channel_index) {
+            *channel_loop = loop_points;
+        }
+    }
+}
+
+/// Filter type of each channel, a read only view of the audio graph that follows filter type
+/// changes from midi and the grid
+#[derive(Clone, Debug)]
+pub struct ChannelFilterTypes {
+    filter_types: Arc<RwLock<Vec<FilterType>>>,
+}
+
+impl ChannelFilterTypes {
+    pub fn get(&self, channel_index: usize) -> Option<FilterType> {
+        let filter_types = self.filter_types.read().expect("Channel filter types lock");
+        filter_types.get(channel_index).copied()
+    }
+
+    fn set(&self, channel_index: usize, filter_type: FilterType) {
+        let mut filter_types = self
+            .filter_types
+            .write()
+            .expect("Channel filter types lock");
+        if let Some(channel_filter_type) = filter_types.get_mut(channel_index) {
+            *channel_filter_type = filter_type;
+        }
     }
 }
 
@@ -85,23 +837,116 @@ pub struct AudioGraph {
     channels: Vec<AudioGraphChannel>,
     volume: GainNode,
     context: AudioContext,
+    delay_bus: DelayBus,
+    reverb_bus: ReverbBus,
+    /// Only read through the meter taps, kept so the limiter lives as long as the graph
+    _limiter: Limiter,
+    meter_taps: Arc<Mutex<MeterTaps>>,
+    lfos: Vec<Lfo>,
+    sampler: Option<Sampler>,
+    recorder: Option<Recorder>,
+    recording_state: RecordingState,
+    channel_loops: ChannelLoops,
+    channel_filter_types: ChannelFilterTypes,
+    buffer_cache: BufferCache,
+    settings: Settings,
+    /// Watches the audio context once it has been started, carried across rebuilds
+    watchdog: Option<Watchdog>,
+    control_tx: Sender<ControlMessage>,
+    /// Latest message for each param, replayed after the graph is rebuilt
+    param_history: Vec<ControlMessage>,
 }
 
 impl AudioGraph {
-    pub fn new(settings: &Settings) -> Self {
-        let context = AudioContext::default();
+    /// `control_tx` is where the graph's own threads send messages back to the control thread
+    pub fn new(settings: &Settings, control_tx: Sender<ControlMessage>) -> Result<Self, Error> {
+        let context = open_context(context_options(settings.audio())?)?;
+        Self::with_context(context, settings, control_tx)
+    }
+
+    fn with_context(
+        context: AudioContext,
+        settings: &Settings,
+        control_tx: Sender<ControlMessage>,
+    ) -> Result<Self, Error> {
+        let limiter = Limiter::new(&context, &context.destination(), settings.limiter());
+        let master_meter = Tap::new(&context, limiter.output());
         let volume = context.create_gain();
-        volume.connect(&context.destination());
+        volume.connect(limiter.input());
+
+        // effects return into the master volume so their tails fade with everything else
+        let delay_bus = DelayBus::new(&context, &volume, settings.effects());
+        let reverb_bus = ReverbBus::new(&context, &volume, settings.effects())?;
 
-        let channels: Vec<AudioGraphChannel> = (0..settings.channel_count())
-            .map(|_| AudioGraphChannel::new(&context, &volume))
+        let channels: Vec<AudioGraphChannel> = settings
+            .channels()
+            .iter()
+            .map(|channel_settings| {
+                AudioGraphChannel::new(
+                    &context,
+                    &volume,
+                    &delay_bus,
+                    &reverb_bus,
+                    channel_settings,
+                    settings.bpm(),
+                    control_tx.clone(),
+                )
+            })
             .collect();
 
-        Self {
+        let lfos: Vec<Lfo> = settings
+            .lfos()
+            .iter()
+            .map(|lfo_settings| Lfo::new(&context, lfo_settings))
+            .collect();
+
+        for lfo in &lfos {
+            let (channel_index, target) = lfo.target();
+            lfo.output()
+                .connect(channels[channel_index].modulation_input(target));
+        }
+
+        let buffer_cache = BufferCache::new(settings.cache().max_size(), context.sample_rate());
+
+        let meter_taps = MeterTaps {
+            master: master_meter,
+            channels: channels
+                .iter()
+                .map(|channel| channel.meter.clone())
+                .collect(),
+            gain_reduction: limiter.gain_reduction(),
+        };
+
+        Ok(Self {
             context,
             channels,
             volume,
-        }
+            delay_bus,
+            reverb_bus,
+            _limiter: limiter,
+            meter_taps: Arc::new(Mutex::new(meter_taps)),
+            lfos,
+            sampler: None,
+            recorder: None,
+            recording_state: RecordingState::default(),
+            channel_loops: ChannelLoops {
+                loops: Arc::new(RwLock::new(vec![(0.0, 1.0); settings.channel_count()])),
+            },
+            channel_filter_types: ChannelFilterTypes {
+                filter_types: Arc::new(RwLock::new(
+                    settings
+                        .channels()
+                        .iter()
+                        .map(|channel| channel.filter_type())
+                        .collect(),
+                )),
+            },
+            buffer_cache,
+            settings: settings.clone(),
+            watchdog: None,
+            control_tx,
+            param_history: vec![],
+        })
     }
 
     pub fn mute_all(&self) {
@@ -111,18 +956,360 @@ impl AudioGraph {
             .linear_ramp_to_value_at_time(0., now + 0.2);
     }
 
+    /// Metering service reading the master output, each channel and the limiter
+    pub fn metering(&self) -> Metering {
+        Metering::new(self.meter_taps.clone())
+    }
+
+    /// A view of what is being recorded that follows recordings as they start and stop
+    pub fn recording_state(&self) -> RecordingState {
+        self.recording_state.clone()
+    }
+
+    /// A view of each channel's loop that follows loop changes and newly loaded samples
+    pub fn channel_loops(&self) -> ChannelLoops {
+        self.channel_loops.clone()
+    }
+
+    /// A view of each channel's filter type that follows filter type changes
+    pub fn channel_filter_types(&self) -> ChannelFilterTypes {
+        self.channel_filter_types.clone()
+    }
+
+    /// Watch the audio context, sending `RebuildAudioGraph` until the graph is rebuilt if it is
+    /// lost
+    pub fn start_watchdog(&mut self) {
+        self.watchdog = Some(spawn_watchdog(
+            self.context.base().clone(),
+            self.control_tx.clone(),
+        ));
+    }
+
+    /// Whether the audio context has been lost, or the graph is rendering without an output
+    /// device after a rebuild failed to open one
+    pub fn needs_rebuild(&self) -> bool {
+        self.watchdog
+            .as_ref()
+            .map(|watchdog| watchdog.is_lost())
+            .unwrap_or(true)
+    }
+
+    /// Remember a param message so it can be replayed after a rebuild, replacing any earlier
+    /// value for the same param
+    pub fn remember_param(&mut self, msg: ControlMessage) {
+        let Some(key) = msg.param_key() else {
+            return;
+        };
+        self.param_history
+            .retain(|previous| previous.param_key() != Some(key));
+        self.param_history.push(msg);
+    }
+
+    pub fn param_history(&self) -> Vec<ControlMessage> {
+        self.param_history.clone()
+    }
+
+    /// Replace the graph with one on a new audio context, reloading each channel's sample and
+    /// carrying on any recordings and the loop controls. Other params are left at their settings
+    /// values, replay `param_history` to restore them.
+    ///
+    /// The old context is closed first so its output device is free to be opened again. If no
+    /// device can be opened the graph renders without one and the error is returned, the watchdog
+    /// keeps asking for rebuilds until one succeeds.
+    pub fn rebuild(&mut self) -> Result<(), Error> {
+        // lfos and granulators schedule on the context from their own threads, which end once
+        // they are dropped
+        self.lfos.clear();
+        for channel in &mut self.channels {
+            channel.granulator = None;
+        }
+        // closing the context ends the recorder's stream, read whether it was still writing first
+        let recording_master = self.recording_state.master();
+        if self.context.state() != AudioContextState::Closed {
+            self.context.close_sync();
+        }
+
+        let control_tx = self.control_tx.clone();
+        let (rebuilt, result) = match AudioGraph::new(&self.settings, control_tx.clone()) {
+            Ok(rebuilt) => (rebuilt, Ok(())),
+            Err(err) => {
+                let context = AudioContext::new(render_only_options(self.settings.audio()));
+                (
+                    AudioGraph::with_context(context, &self.settings, control_tx)?,
+                    Err(err),
+                )
+            }
+        };
+        let mut previous = mem::replace(self, rebuilt);
+
+        // the metering service keeps reading through the same taps handle
+        *previous.meter_taps.lock().expect("Meter taps lock") =
+            self.meter_taps.lock().expect("Meter taps lock").clone();
+        self.meter_taps = previous.meter_taps.clone();
+        self.param_history = previous.param_history.clone();
+        self.buffer_cache = previous.buffer_cache.clone();
+        self.recording_state = previous.recording_state.clone();
+        self.channel_loops = previous.channel_loops.clone();
+        // the rebuilt channels start from their settings until params are replayed
+        self.channel_filter_types = previous.channel_filter_types.clone();
+        for (channel_index, channel) in self.channels.iter().enumerate() {
+            self.channel_filter_types
+                .set(channel_index, channel.filter_type);
+        }
+        self.watchdog = previous.watchdog.clone();
+
+        if let Some(sampler) = previous.sampler.take() {
+            self.sampler = Some(sampler.restart(&self.context));
+        }
+        if let Some(recorder) = previous.recorder.take() {
+            // a recorder that stopped itself after failing to write is left stopped
+            if recording_master {
+                match recorder.restart(&self.context, &self.volume, self.settings.recorder()) {
+                    Ok(recorder) => self.recorder = Some(recorder),
+                    Err(err) => println!("master recording stopped while rebuilding: {}", err),
+                }
+            }
+        }
+
+        for (channel_index, previous_channel) in previous.channels.iter().enumerate() {
+            // the loop controls go in before the sample, so loop points read from it still win
+            let channel = &mut self.channels[channel_index];
+            channel.control_loop = previous_channel.control_loop;
+            channel.reset_loop();
+            self.update_channel_loops(channel_index);
+
+            let Some(path) = &previous_channel.sample_path else {
+                continue;
+            };
+            match self.load_channel(channel_index, path) {
+                Ok(()) => self.channels[channel_index].play(&self.context),
+                Err(err) => println!("failed to reload {:?}: {}", path, err),
+            }
+        }
+
+        if let (Some(watchdog), Ok(())) = (&self.watchdog, &result) {
+            watchdog.watch(self.context.base().clone());
+        }
+
+        result
+    }
+
+    pub fn set_delay_return(&self, value: f32) {
+        self.delay_bus.set_return_level(value);
+    }
+
+    pub fn set_reverb_return(&self, value: f32) {
+        self.reverb_bus.set_return_level(value);
+    }
+
+    pub fn get_lfo(&self, lfo_index: usize) -> Option<&Lfo> {
+        self.lfos.get(lfo_index)
+    }
+
+    /// Move an lfo onto another channel param, returns `None` if either doesn't exist
+    pub fn set_lfo_target(
+        &mut self,
+        lfo_index: usize,
+        channel_index: usize,
+        target: LfoTarget,
+    ) -> Option<()> {
+        let channel = self.channels.get(channel_index)?;
+        let lfo = self.lfos.get_mut(lfo_index)?;
+
+        lfo.output().disconnect();
+        lfo.set_target(channel_index, target);
+        lfo.output().connect(channel.modulation_input(target));
+
+        Some(())
+    }
+
+    pub fn stop_faded_streams(&mut self) {
+        for channel in &mut self.channels {
+            channel.stop_faded_streams();
+        }
+    }
+
+    /// Swap in the loops stretched on the channels' stretcher threads that have finished
+    pub fn finish_stretches(&mut self) {
+        for channel in &mut self.channels {
+            channel.finish_stretch();
+        }
+    }
+
+    pub fn set_bpm(&mut self, bpm: f32) {
+        for channel in self.channels.iter_mut() {
+            channel.set_bpm(bpm);
+        }
+    }
+
+    pub fn set_playback_mode(
+        &mut self,
+        channel_index: usize,
+        playback_mode: PlaybackMode,
+    ) -> Option<()> {
+        let channel = self.channels.get_mut(channel_index)?;
+        channel.set_playback_mode(&self.context, playback_mode);
+        Some(())
+    }
+
+    pub fn set_filter_type(&mut self, channel_index: usize, filter_type: FilterType) -> Option<()> {
+        self.channels
+            .get_mut(channel_index)?
+            .set_filter_type(filter_type);
+        self.channel_filter_types.set(channel_index, filter_type);
+        Some(())
+    }
+
+    pub fn set_loop_start(&mut self, channel_index: usize, value: f32) -> Option<()> {
+        self.channels.get_mut(channel_index)?.set_loop_start(value);
+        self.update_channel_loops(channel_index);
+        Some(())
+    }
+
+    pub fn set_loop_length(&mut self, channel_index: usize, value: f32) -> Option<()> {
+        self.channels.get_mut(channel_index)?.set_loop_length(value);
+        self.update_channel_loops(channel_index);
+        Some(())
+    }
+
+    fn update_channel_loops(&self, channel_index: usize) {
+        if let Some(channel) = self.channels.get(channel_index) {
+            self.channel_loops
+                .set(channel_index, (channel.loop_start, channel.loop_length));
+        }
+    }
+
+    pub fn trigger_channel(&mut self, channel_index: usize) -> Option<()> {
+        let channel = self.channels.get_mut(channel_index)?;
+        channel.trigger(&self.context);
+        Some(())
+    }
+
     pub fn get_channel(&self, channel_index: usize) -> Option<&AudioGraphChannel> {
         self.channels.get(channel_index)
     }
 
-    pub fn load_and_play_for_channel(&mut self, channel_index: usize, file_path: &PathBuf) {
+    pub fn get_channel_mut(&mut self, channel_index: usize) -> Option<&mut AudioGraphChannel> {
+        self.channels.get_mut(channel_index)
+    }
+
+    /// Decode sample files in the background so they are ready by the time they are selected,
+    /// unless preloading is turned off
+    pub fn preload(&self, paths: Vec<PathBuf>) {
+        if !self.settings.cache().preload() {
+            return;
+        }
+        let paths = paths
+            .into_iter()
+            .filter(|path| !self.is_streamed(path))
+            .collect();
+        self.buffer_cache.preload(paths);
+    }
+
+    pub fn load_and_play_for_channel(
+        &mut self,
+        channel_index: usize,
+        file_path: &Path,
+    ) -> Result<(), Error> {
+        self.load_channel(channel_index, file_path)?;
         let channel = self
             .channels
             .get_mut(channel_index)
             .expect("Channel index in range");
-        channel
-            .load(&self.context, file_path)
-            .expect("Sample file loaded into audio channel");
-        channel.play();
+        channel.play(&self.context);
+        Ok(())
+    }
+
+    /// Load a sample file into a channel, streaming it from disk if it is too big to decode.
+    /// Granular channels always decode, grains are read from anywhere in the sample.
+    fn load_channel(&mut self, channel_index: usize, file_path: &Path) -> Result<(), Error> {
+        let streamed = self.is_streamed(file_path);
+        let channel = self
+            .channels
+            .get_mut(channel_index)
+            .expect("Channel index in range");
+
+        if streamed && channel.granulator.is_none() {
+            channel.load_stream(&self.context, file_path)?;
+        } else {
+            let buffers = self.buffer_cache.get_or_decode(file_path)?;
+            channel.load(&self.context, file_path, buffers);
+        }
+        self.update_channel_loops(channel_index);
+        Ok(())
+    }
+
+    fn is_streamed(&self, file_path: &Path) -> bool {
+        fs::metadata(file_path)
+            .map(|metadata| metadata.len() >= self.settings.cache().stream_threshold())
+            .unwrap_or(false)
+    }
+
+    /// Start recording the audio input for a channel, ignored if a recording is in progress
+    pub fn start_recording(&mut self, channel_index: usize) {
+        if let Some(sampler) = &self.sampler {
+            println!(
+                "already recording channel {}, ignoring request to record channel {}",
+                sampler.channel_index(),
+                channel_index
+            );
+            return;
+        }
+
+        self.sampler = Some(Sampler::start(&self.context, channel_index));
+        self.recording_state.set_channel(Some(channel_index));
+    }
+
+    /// Stop recording a channel, saving the recording to `sample_dir` and looping it on the channel
+    pub fn stop_recording(&mut self, channel_index: usize, sample_dir: &Path) -> Result<(), Error> {
+        match &self.sampler {
+            Some(sampler) if sampler.channel_index() == channel_index => {}
+            _ => return Ok(()),
+        }
+
+        let sampler = self.sampler.take().expect("Sampler is recording");
+        self.recording_state.set_channel(None);
+        let (path, buffer) = sampler.stop(sample_dir)?;
+        let channel = self
+            .channels
+            .get_mut(channel_index)
+            .expect("Channel index in range");
+        channel.reset_loop();
+        channel.load_buffers(&self.context, Arc::new(SampleBuffers::new(buffer)));
+        channel.sample_path = Some(path);
+        channel.play(&self.context);
+        self.update_channel_loops(channel_index);
+
+        Ok(())
+    }
+
+    /// Start recording the master output to disk, ignored if a recording is in progress
+    pub fn start_master_recording(&mut self) -> Result<(), Error> {
+        if self.recorder.is_some() {
+            if self.recording_state.master() {
+                println!("already recording master output");
+                return Ok(());
+            }
+            // the recorder stopped itself after failing to write, clear it out first
+            self.stop_master_recording()?;
+        }
+
+        let recorder = Recorder::start(
+            &self.context,
+            &self.volume,
+            self.settings.recorder(),
+            self.recording_state.master.clone(),
+        )?;
+        self.recorder = Some(recorder);
+
+        Ok(())
+    }
+
+    pub fn stop_master_recording(&mut self) -> Result<(), Error> {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.stop(&self.volume)?;
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::SystemTime;
+
+use web_audio_api::context::{BaseAudioContext, OfflineAudioContext};
+use web_audio_api::AudioBuffer;
+
+use crate::audio_graph::Error;
+
+/// Size and modification time of a sample file, a file overwritten in place gets a new stamp
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn read(path: &Path) -> Result<Self, Error> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// A decoded sample and a reversed copy for playing it backwards, which is only built once it is
+/// needed
+pub struct SampleBuffers {
+    forward: AudioBuffer,
+    reversed: OnceLock<AudioBuffer>,
+}
+
+impl SampleBuffers {
+    pub fn new(forward: AudioBuffer) -> Self {
+        Self {
+            forward,
+            reversed: OnceLock::new(),
+        }
+    }
+
+    pub fn forward(&self) -> &AudioBuffer {
+        &self.forward
+    }
+
+    /// The sample reversed, built the first time it is asked for
+    pub fn reversed(&self) -> &AudioBuffer {
+        self.reversed.get_or_init(|| reverse_buffer(&self.forward))
+    }
+
+    /// Bytes held by the sample and its reversed copy, if it has been built
+    fn size(&self) -> usize {
+        buffer_size(&self.forward) + self.reversed.get().map_or(0, buffer_size)
+    }
+}
+
+struct CacheEntry {
+    buffers: Arc<SampleBuffers>,
+    /// Stamp of the file when it was decoded
+    stamp: FileStamp,
+    /// Value of the cache's clock when the entry was last used, the lowest is evicted first
+    last_used: u64,
+}
+
+struct CacheState {
+    entries: HashMap<PathBuf, CacheEntry>,
+    max_size: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    /// The buffer decoded from `path`, as long as the file hasn't changed since. A stale buffer is
+    /// dropped.
+    fn get(&mut self, path: &Path, stamp: FileStamp) -> Option<Arc<SampleBuffers>> {
+        if !self.contains(path, stamp) {
+            self.remove(path);
+            return None;
+        }
+
+        self.clock += 1;
+        let entry = self.entries.get_mut(path)?;
+        entry.last_used = self.clock;
+        Some(entry.buffers.clone())
+    }
+
+    fn contains(&self, path: &Path, stamp: FileStamp) -> bool {
+        self.entries
+            .get(path)
+            .is_some_and(|entry| entry.stamp == stamp)
+    }
+
+    /// Bytes held by every entry. Reversed copies are built after their sample was added, so this
+    /// is added up each time rather than kept.
+    fn size(&self) -> usize {
+        self.entries
+            .values()
+            .map(|entry| entry.buffers.size())
+            .sum()
+    }
+
+    /// Add a sample, evicting the least recently used until it fits. Samples bigger than the
+    /// whole cache aren't kept.
+    fn insert(&mut self, path: PathBuf, stamp: FileStamp, buffers: Arc<SampleBuffers>) {
+        let size = buffers.size();
+        if size > self.max_size || self.contains(&path, stamp) {
+            return;
+        }
+        // a buffer decoded from an older version of the file
+        self.remove(&path);
+
+        while self.size() + size > self.max_size {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+
+        self.clock += 1;
+        self.entries.insert(
+            path,
+            CacheEntry {
+                buffers,
+                stamp,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    fn has_room_for(&self, buffers: &SampleBuffers) -> bool {
+        self.size() + buffers.size() <= self.max_size
+    }
+}
+
+/// Decoded samples keyed by path, decoded again when the file changes on disk. Shared with the preloading thread
+#[derive(Clone)]
+pub struct BufferCache {
+    state: Arc<Mutex<CacheState>>,
+    sample_rate: f32,
+}
+
+impl BufferCache {
+    /// Buffers are decoded at `sample_rate`, which should match the audio context
+    pub fn new(max_size: usize, sample_rate: f32) -> Self {
+        let state = CacheState {
+            entries: HashMap::new(),
+            max_size,
+            clock: 0,
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            sample_rate,
+        }
+    }
+
+    /// The decoded sample at `path`, decoding it and adding it to the cache if it isn't there or
+    /// the file has changed
+    pub fn get_or_decode(&self, path: &Path) -> Result<Arc<SampleBuffers>, Error> {
+        let stamp = FileStamp::read(path)?;
+        if let Some(buffers) = self.state().get(path, stamp) {
+            return Ok(buffers);
+        }
+
+        let buffers = Arc::new(SampleBuffers::new(decode(path, self.sample_rate)?));
+        self.state()
+            .insert(path.to_path_buf(), stamp, buffers.clone());
+        Ok(buffers)
+    }
+
+    /// Decode samples in the background until the cache is full, so the first samples in `paths`
+    /// are ready when they are selected
+    pub fn preload(&self, paths: Vec<PathBuf>) {
+        let cache = self.clone();
+
+        thread::spawn(move || {
+            for path in paths {
+                let Ok(stamp) = FileStamp::read(&path) else {
+                    continue;
+                };
+                if cache.state().contains(&path, stamp) {
+                    continue;
+                }
+
+                let buffers = match decode(&path, cache.sample_rate) {
+                    Ok(buffer) => Arc::new(SampleBuffers::new(buffer)),
+                    Err(err) => {
+                        println!("failed to preload {:?}: {}", path, err);
+                        continue;
+                    }
+                };
+
+                let mut state = cache.state();
+                // preloading never evicts, samples that have been played matter more
+                if !state.has_room_for(&buffers) {
+                    println!("sample cache full, stopped preloading at {:?}", path);
+                    break;
+                }
+                state.insert(path, stamp, buffers);
+            }
+        });
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().expect("Buffer cache lock")
+    }
+}
+
+fn decode(path: &Path, sample_rate: f32) -> Result<AudioBuffer, Error> {
+    let file = File::open(path)?;
+    // an offline context decodes and resamples without needing the audio context's thread
+    let context = OfflineAudioContext::new(1, 1, sample_rate);
+    Ok(context.decode_audio_data_sync(file)?)
+}
+
+fn reverse_buffer(buffer: &AudioBuffer) -> AudioBuffer {
+    let samples = (0..buffer.number_of_channels())
+        .map(|channel| {
+            buffer
+                .get_channel_data(channel)
+                .iter()
+                .rev()
+                .copied()
+                .collect()
+        })
+        .collect();
+    AudioBuffer::from(samples, buffer.sample_rate())
+}
+
+fn buffer_size(buffer: &AudioBuffer) -> usize {
+    buffer.length() * buffer.number_of_channels() * std::mem::size_of::<f32>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mono sample taking up `size` bytes
+    fn buffer(size: usize) -> Arc<SampleBuffers> {
+        Arc::new(SampleBuffers::new(AudioBuffer::from(
+            vec![vec![0.0; size / 4]],
+            48000.0,
+        )))
+    }
+
+    const STAMP: FileStamp = FileStamp {
+        len: 1000,
+        modified: Some(SystemTime::UNIX_EPOCH),
+    };
+
+    fn state(max_size: usize) -> CacheState {
+        CacheState {
+            entries: HashMap::new(),
+            max_size,
+            clock: 0,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut state = state(300);
+        state.insert("a".into(), STAMP, buffer(100));
+        state.insert("b".into(), STAMP, buffer(100));
+        state.insert("c".into(), STAMP, buffer(100));
+        assert!(state.get(Path::new("a"), STAMP).is_some());
+
+        state.insert("d".into(), STAMP, buffer(100));
+
+        assert!(state.get(Path::new("b"), STAMP).is_none());
+        assert!(state.get(Path::new("a"), STAMP).is_some());
+        assert!(state.get(Path::new("c"), STAMP).is_some());
+        assert!(state.get(Path::new("d"), STAMP).is_some());
+    }
+
+    #[test]
+    fn evicts_until_new_buffer_fits() {
+        let mut state = state(300);
+        state.insert("a".into(), STAMP, buffer(100));
+        state.insert("b".into(), STAMP, buffer(100));
+        state.insert("c".into(), STAMP, buffer(100));
+
+        state.insert("d".into(), STAMP, buffer(200));
+
+        assert!(state.get(Path::new("a"), STAMP).is_none());
+        assert!(state.get(Path::new("b"), STAMP).is_none());
+        assert!(state.get(Path::new("c"), STAMP).is_some());
+        assert_eq!(state.size(), 300);
+    }
+
+    #[test]
+    fn stays_within_byte_budget() {
+        let mut state = state(250);
+        for name in ["a", "b", "c", "d", "e"] {
+            state.insert(name.into(), STAMP, buffer(100));
+            assert!(state.size() <= state.max_size);
+        }
+        assert_eq!(state.size(), 200);
+        assert_eq!(state.entries.len(), 2);
+    }
+
+    #[test]
+    fn skips_buffers_bigger_than_the_cache() {
+        let mut state = state(300);
+        state.insert("a".into(), STAMP, buffer(100));
+
+        state.insert("b".into(), STAMP, buffer(400));
+
+        assert!(state.get(Path::new("a"), STAMP).is_some());
+        assert!(state.get(Path::new("b"), STAMP).is_none());
+        assert_eq!(state.size(), 100);
+    }
+
+    #[test]
+    fn room_for_buffers_within_budget() {
+        let mut state = state(300);
+        state.insert("a".into(), STAMP, buffer(200));
+
+        assert!(state.has_room_for(&buffer(100)));
+        assert!(!state.has_room_for(&buffer(104)));
+    }
+
+    #[test]
+    fn drops_buffers_of_changed_files() {
+        let mut state = state(300);
+        state.insert("a".into(), STAMP, buffer(100));
+        let changed = FileStamp { len: 2000, ..STAMP };
+
+        assert!(state.get(Path::new("a"), changed).is_none());
+        assert_eq!(state.size(), 0);
+
+        state.insert("a".into(), changed, buffer(200));
+        assert!(state.get(Path::new("a"), changed).is_some());
+        assert_eq!(state.size(), 200);
+    }
+
+    #[test]
+    fn replaces_buffers_of_changed_files() {
+        let mut state = state(300);
+        state.insert("a".into(), STAMP, buffer(100));
+        let changed = FileStamp {
+            modified: None,
+            ..STAMP
+        };
+
+        state.insert("a".into(), changed, buffer(200));
+
+        assert!(state.get(Path::new("a"), changed).is_some());
+        assert_eq!(state.size(), 200);
+        assert_eq!(state.entries.len(), 1);
+    }
+
+    #[test]
+    fn counts_reversed_copies() {
+        let mut state = state(300);
+        let a = buffer(100);
+        state.insert("a".into(), STAMP, a.clone());
+        assert_eq!(state.size(), 100);
+
+        a.reversed();
+        assert_eq!(state.size(), 200);
+
+        state.insert("b".into(), STAMP, buffer(200));
+        assert!(state.get(Path::new("a"), STAMP).is_none());
+        assert_eq!(state.size(), 200);
+    }
+}
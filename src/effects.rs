@@ -0,0 +1,94 @@
+use std::fs::File;
+
+use web_audio_api::context::{AudioContext, BaseAudioContext};
+use web_audio_api::node::{AudioNode, ConvolverNode, DelayNode, GainNode};
+
+use crate::audio_graph::Error;
+use crate::settings::EffectsSettings;
+use crate::MAX_DELAY_TIME;
+
+/// Shared feedback delay that channels send into
+pub struct DelayBus {
+    input: GainNode,
+    _delay: DelayNode,
+    _feedback: GainNode,
+    output: GainNode,
+}
+
+impl DelayBus {
+    pub fn new(context: &AudioContext, destination: &GainNode, settings: &EffectsSettings) -> Self {
+        let output = context.create_gain();
+        output.connect(destination);
+
+        let delay = context.create_delay(MAX_DELAY_TIME);
+        delay.delay_time().set_value(settings.delay_time() as f32);
+        delay.connect(&output);
+
+        let feedback = context.create_gain();
+        feedback.gain().set_value(settings.delay_feedback());
+        delay.connect(&feedback);
+        feedback.connect(&delay);
+
+        let input = context.create_gain();
+        input.connect(&delay);
+
+        Self {
+            input,
+            _delay: delay,
+            _feedback: feedback,
+            output,
+        }
+    }
+
+    pub fn input(&self) -> &GainNode {
+        &self.input
+    }
+
+    pub fn set_return_level(&self, value: f32) {
+        self.output.gain().set_value(value);
+    }
+}
+
+/// Shared convolution reverb that channels send into
+pub struct ReverbBus {
+    input: GainNode,
+    _convolver: ConvolverNode,
+    output: GainNode,
+}
+
+impl ReverbBus {
+    pub fn new(
+        context: &AudioContext,
+        destination: &GainNode,
+        settings: &EffectsSettings,
+    ) -> Result<Self, Error> {
+        let output = context.create_gain();
+        output.connect(destination);
+
+        // without an impulse response the convolver outputs silence
+        let convolver = context.create_convolver();
+        if let Some(path) = settings.reverb_impulse() {
+            let file = File::open(path)?;
+            let impulse = context.decode_audio_data_sync(file)?;
+            convolver.set_buffer(impulse);
+        }
+        convolver.connect(&output);
+
+        let input = context.create_gain();
+        input.connect(&convolver);
+
+        Ok(Self {
+            input,
+            _convolver: convolver,
+            output,
+        })
+    }
+
+    pub fn input(&self) -> &GainNode {
+        &self.input
+    }
+
+    pub fn set_return_level(&self, value: f32) {
+        self.output.gain().set_value(value);
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+use web_audio_api::context::{AudioContext, BaseAudioContext};
+use web_audio_api::node::{AudioNode, AudioScheduledSourceNode, GainNode};
+use web_audio_api::AudioBuffer;
+
+/// How far ahead of the audio clock grains are scheduled
+const SCHEDULE_AHEAD_TIME: f64 = 0.1;
+const SCHEDULER_INTERVAL: Duration = Duration::from_millis(25);
+/// Number of points used to describe the grain window
+const WINDOW_LENGTH: usize = 64;
+
+pub const MIN_GRAIN_SIZE: f32 = 0.01;
+pub const MAX_GRAIN_SIZE: f32 = 0.5;
+pub const MIN_GRAIN_DENSITY: f32 = 1.0;
+pub const MAX_GRAIN_DENSITY: f32 = 100.0;
+pub const MAX_PITCH_JITTER: f32 = 12.0;
+
+/// An f32 that can be shared with the scheduling thread
+struct SharedValue(AtomicU32);
+
+impl SharedValue {
+    fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::SeqCst))
+    }
+
+    fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::SeqCst);
+    }
+}
+
+struct GrainParams {
+    /// Where grains are taken from, from 0.0 (start) to 1.0 (end of the buffer)
+    position: SharedValue,
+    /// Length of each grain in seconds
+    size: SharedValue,
+    /// Grains started per second
+    density: SharedValue,
+    /// Random offset applied to the position of each grain, as a fraction of the buffer
+    spray: SharedValue,
+    /// Random pitch offset applied to each grain, in semitones either side of the rate
+    pitch_jitter: SharedValue,
+    rate: SharedValue,
+}
+
+/// Channel engine that sprays overlapping windowed grains taken from the loaded buffer
+pub struct Granulator {
+    params: Arc<GrainParams>,
+    buffer: Arc<Mutex<Option<AudioBuffer>>>,
+}
+
+impl Granulator {
+    pub fn new(context: &AudioContext, destination: &dyn AudioNode) -> Self {
+        let output = context.create_gain();
+        output.connect(destination);
+
+        let params = Arc::new(GrainParams {
+            position: SharedValue::new(0.5),
+            size: SharedValue::new(0.1),
+            density: SharedValue::new(20.0),
+            spray: SharedValue::new(0.05),
+            pitch_jitter: SharedValue::new(0.0),
+            rate: SharedValue::new(1.0),
+        });
+        let buffer = Arc::new(Mutex::new(None));
+
+        spawn_scheduler(output, params.clone(), buffer.clone());
+
+        Self { params, buffer }
+    }
+
+    /// Swap the buffer grains are taken from, grains already playing finish on the old buffer
+    pub fn set_buffer(&self, buffer: AudioBuffer) {
+        *self.buffer.lock().expect("Grain buffer lock") = Some(buffer);
+    }
+
+    pub fn set_position(&self, value: f32) {
+        self.params.position.set(value.clamp(0.0, 1.0));
+    }
+
+    pub fn set_size(&self, value: f32) {
+        self.params
+            .size
+            .set(value.clamp(MIN_GRAIN_SIZE, MAX_GRAIN_SIZE));
+    }
+
+    pub fn set_density(&self, value: f32) {
+        self.params
+            .density
+            .set(value.clamp(MIN_GRAIN_DENSITY, MAX_GRAIN_DENSITY));
+    }
+
+    pub fn set_spray(&self, value: f32) {
+        self.params.spray.set(value.clamp(0.0, 1.0));
+    }
+
+    pub fn set_pitch_jitter(&self, value: f32) {
+        self.params
+            .pitch_jitter
+            .set(value.clamp(0.0, MAX_PITCH_JITTER));
+    }
+
+    pub fn set_rate(&self, value: f32) {
+        self.params.rate.set(value);
+    }
+}
+
+/// Schedule grains slightly ahead of the audio clock so they start on time, until the granulator
+/// is dropped
+fn spawn_scheduler(
+    output: GainNode,
+    params: Arc<GrainParams>,
+    buffer: Arc<Mutex<Option<AudioBuffer>>>,
+) {
+    thread::spawn(move || {
+        let mut rng = rand::thread_rng();
+        let window = hann_window();
+        let mut next_grain_time = 0.0;
+
+        while Arc::strong_count(&params) > 1 {
+            let current = buffer.lock().expect("Grain buffer lock").clone();
+            if let Some(buffer) = current {
+                let now = output.context().current_time();
+                next_grain_time = f64::max(next_grain_time, now);
+
+                while next_grain_time < now + SCHEDULE_AHEAD_TIME {
+                    let size = params.size.get() as f64;
+                    let spray = params.spray.get();
+                    let position = (params.position.get() + rng.gen_range(-spray..=spray))
+                        .rem_euclid(1.0) as f64;
+                    let jitter = params.pitch_jitter.get();
+                    let semitones = rng.gen_range(-jitter..=jitter);
+                    let rate = params.rate.get() * (semitones / 12.0).exp2();
+                    let density = params.density.get();
+                    // grains are uncorrelated, so their power rather than their level adds up
+                    // where they overlap
+                    let overlap = (size as f32 * density).max(1.0);
+                    let level = overlap.sqrt().recip();
+                    let curve: Vec<f32> = window.iter().map(|value| value * level).collect();
+
+                    let context = output.context();
+                    let grain = context.create_gain();
+                    grain
+                        .gain()
+                        .set_value_curve_at_time(&curve, next_grain_time, size);
+                    grain.connect(&output);
+
+                    let source = context.create_buffer_source();
+                    source.playback_rate().set_value(rate);
+                    source.set_loop(true);
+                    source.set_buffer(buffer.clone());
+                    source.connect(&grain);
+                    source.start_at_with_offset(next_grain_time, position * buffer.duration());
+                    source.stop_at(next_grain_time + size);
+
+                    next_grain_time += 1.0 / density as f64;
+                }
+            }
+
+            thread::sleep(SCHEDULER_INTERVAL);
+        }
+    });
+}
+
+fn hann_window() -> Vec<f32> {
+    (0..WINDOW_LENGTH)
+        .map(|index| {
+            let position = index as f32 / (WINDOW_LENGTH - 1) as f32;
+            0.5 - 0.5 * (2.0 * std::f32::consts::PI * position).cos()
+        })
+        .collect()
+}
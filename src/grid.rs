@@ -1,4 +1,11 @@
-use crate::{message::ControlMessage, settings::Settings};
+use crate::{
+    audio_graph::{ChannelFilterTypes, ChannelLoops, RecordingState},
+    message::ControlMessage,
+    meter::{Level, Levels},
+    sample_manager::SampleCounts,
+    settings::{FilterType, Settings},
+    MAX_CHANNEL_COUNT,
+};
 use monome::{KeyDirection, Monome, MonomeDeviceType, MonomeEvent};
 use std::{
     println,
@@ -7,29 +14,107 @@ use std::{
 };
 
 const SAMPLE_GRID_X: usize = 8;
-const SAMPLE_GRID_Y: usize = 6;
+/// The row below the sample selector is the only one left on the left half for the page keys, the
+/// filter type, bank, channel and record keys take up the last two
+const SAMPLE_GRID_Y: usize = 5;
+/// Samples on each page of the sample selector
 const SAMPLE_GRID: usize = SAMPLE_GRID_X * SAMPLE_GRID_Y;
+/// Row below the sample selector with a key per page followed by the page up and down keys
+const PAGE_ROW: usize = SAMPLE_GRID_Y;
+/// Page keys show the block of this many pages holding the shown page, so banks with more pages
+/// than keys wrap onto them
+const PAGE_INDICATORS: usize = 6;
+const PAGE_UP_KEY: (usize, usize) = (6, PAGE_ROW);
+const PAGE_DOWN_KEY: (usize, usize) = (7, PAGE_ROW);
+const FILTER_TYPE_ROW: usize = 6;
+const RECORD_KEY: (usize, usize) = (7, 7);
+const MASTER_RECORD_KEY: (usize, usize) = (6, 7);
+/// Restarts the selected channel's sample, for playing one shots
+const TRIGGER_KEY: (usize, usize) = (5, 7);
+/// Toggles the sample selector between the active bank's samples and the list of banks
+const BANK_KEY: (usize, usize) = (5, 6);
+const PREVIOUS_BANK_KEY: (usize, usize) = (6, 6);
+const NEXT_BANK_KEY: (usize, usize) = (7, 6);
+/// Loop rows are on the right half of the grid, split into this many steps
+const LOOP_STEPS: usize = 8;
+const LOOP_GRID_X: usize = 8;
+const LOOP_START_ROW: usize = 0;
+const LOOP_LENGTH_ROW: usize = 1;
+/// Level meters on the right half of the grid, a row per channel followed by master and the
+/// limiter gain reduction
+const METER_STEPS: usize = 8;
+const CHANNEL_METER_ROW: usize = 2;
+const MASTER_METER_ROW: usize = CHANNEL_METER_ROW + MAX_CHANNEL_COUNT;
+const GAIN_REDUCTION_ROW: usize = MASTER_METER_ROW + 1;
+const METER_ROWS: usize = GAIN_REDUCTION_ROW + 1 - CHANNEL_METER_ROW;
+/// dB shown by each key of a level meter, the last key lights at 0 dBFS
+const LEVEL_STEP_DB: f32 = 6.0;
+/// dB of limiter gain reduction shown by each lit key
+const GAIN_REDUCTION_STEP_DB: f32 = 3.0;
 
 #[derive(Debug)]
 pub enum GridMessage {
     Clear,
+    Levels(Levels),
+    /// The samples shown have changed
+    Redraw,
+}
+
+/// Where a channel is in the sample selector
+#[derive(Clone, Copy, Debug, Default)]
+struct SampleSelection {
+    /// Index of the selected sample in the bank it was picked from
+    sample: usize,
+    /// Bank the selected sample was picked from
+    bank: usize,
+    /// Page of the active bank shown in the sample selector
+    page: usize,
 }
 
 pub struct Grid {
     rx: Receiver<GridMessage>,
     device: Option<Monome>,
-    selected_sample_indexes: Vec<usize>,
+    selected_sample_indexes: Vec<SampleSelection>,
     selected_channel_index: usize,
+    sample_counts: SampleCounts,
+    /// Selected channel's active bank when the sample selector was last drawn
+    drawn_active_bank: usize,
+    /// Selected channel's bank count and samples in its active bank when the sample selector was
+    /// last drawn
+    drawn_sample_counts: (usize, usize),
+    /// The sample selector shows the banks instead of the samples in the active bank
+    showing_banks: bool,
+    channel_filter_types: ChannelFilterTypes,
+    /// Selected channel's filter type when the filter type row was last drawn
+    drawn_filter_type: Option<FilterType>,
+    channel_loops: ChannelLoops,
+    /// Selected channel's loop start and length steps when the loop rows were last drawn
+    drawn_loop_steps: (usize, usize),
+    recording_state: RecordingState,
+    /// Channel being recorded into when the record key was last drawn
+    drawn_recording_channel: Option<usize>,
+    /// Whether the master output was being recorded when the master record key was last drawn
+    drawn_recording_master: bool,
+    levels: Levels,
 }
 
 impl Grid {
-    pub fn new(settings: &Settings) -> (Self, Sender<GridMessage>) {
+    /// Keys are only lit for the banks and samples in `sample_counts`, the loop rows show the
+    /// loops in `channel_loops`, the filter type row shows `channel_filter_types` and the record
+    /// keys are lit while `recording_state` has a recording in progress
+    pub fn new(
+        settings: &Settings,
+        sample_counts: SampleCounts,
+        channel_loops: ChannelLoops,
+        channel_filter_types: ChannelFilterTypes,
+        recording_state: RecordingState,
+    ) -> (Self, Sender<GridMessage>) {
         let device = Monome::enumerate_devices()
             .expect("Monome setup successfully")
             .into_iter()
             .find(|d| d.device_type() == MonomeDeviceType::Grid)
             .and_then(|d| Monome::from_device(&d, "/prefix").ok());
-        let selected_sample_indexes = vec![0; settings.channel_count()];
+        let selected_sample_indexes = vec![SampleSelection::default(); settings.channel_count()];
         let (tx, rx) = channel::<GridMessage>();
         (
             Grid {
@@ -37,6 +122,18 @@ impl Grid {
                 device,
                 selected_sample_indexes,
                 selected_channel_index: 0,
+                sample_counts,
+                drawn_active_bank: 0,
+                drawn_sample_counts: (0, 0),
+                showing_banks: false,
+                channel_filter_types,
+                drawn_filter_type: None,
+                channel_loops,
+                drawn_loop_steps: (0, LOOP_STEPS - 1),
+                recording_state,
+                drawn_recording_channel: None,
+                drawn_recording_master: false,
+                levels: Levels::default(),
             },
             tx,
         )
@@ -48,8 +145,25 @@ impl Grid {
         thread::spawn(move || loop {
             let rx = &self.rx;
 
-            if let Ok(GridMessage::Clear) = rx.try_recv() {
-                self.clear_device();
+            match rx.try_recv() {
+                Ok(GridMessage::Clear) => self.clear_device(),
+                Ok(GridMessage::Redraw) => self.redraw(),
+                Ok(GridMessage::Levels(levels)) => {
+                    let previous_meters = self.map_meters();
+                    self.levels = levels;
+                    // only redraw when a key changes, levels arrive many times a second
+                    if self.map_meters() != previous_meters {
+                        self.redraw();
+                    }
+                }
+                Err(_) => {}
+            }
+            // recordings, loops, filter types and banks can be changed over midi or by loading a
+            // sample, recordings can fail to start and the sample manager refreshes after
+            // recordings are saved as well as when the watcher sees a change, so the keys follow
+            // what the audio graph and sample manager are actually doing
+            if self.is_drawn_state_stale() {
+                self.redraw();
             }
             if let Some(MonomeEvent::GridKey {
                 x,
@@ -74,9 +188,19 @@ impl Grid {
     pub fn redraw(&mut self) {
         let channel_offset = 56;
         let mut left_mask = [0; 64];
-        let right_mask = [0; 64];
+        let mut right_mask = [0; 64];
+        self.drawn_recording_channel = self.recording_state.channel();
+        self.drawn_recording_master = self.recording_state.master();
+        self.drawn_loop_steps = self.loop_steps();
+        self.drawn_active_bank = self.active_bank();
+        self.drawn_filter_type = self.filter_type();
+        self.drawn_sample_counts = (self.bank_count(), self.sample_count());
 
-        self.map_sample_selector()
+        let sample_selector = match self.showing_banks {
+            true => self.map_bank_selector(),
+            false => self.map_sample_selector(),
+        };
+        sample_selector
             .into_iter()
             .enumerate()
             .for_each(|(index, value)| left_mask[index] = value);
@@ -84,10 +208,44 @@ impl Grid {
             .into_iter()
             .enumerate()
             .for_each(|(index, value)| left_mask[index + channel_offset] = value);
+        self.map_filter_type_selector()
+            .into_iter()
+            .enumerate()
+            .for_each(|(index, value)| left_mask[index + FILTER_TYPE_ROW * 8] = value);
+        left_mask[RECORD_KEY.0 + RECORD_KEY.1 * 8] = self.map_record_key();
+        left_mask[MASTER_RECORD_KEY.0 + MASTER_RECORD_KEY.1 * 8] = self.map_master_record_key();
+        self.map_page_row()
+            .into_iter()
+            .enumerate()
+            .for_each(|(index, value)| left_mask[index + PAGE_ROW * 8] = value);
+        left_mask[TRIGGER_KEY.0 + TRIGGER_KEY.1 * 8] = 5;
+        left_mask[BANK_KEY.0 + BANK_KEY.1 * 8] = self.map_bank_key();
+        let bank_step_key = self.map_bank_step_key();
+        left_mask[PREVIOUS_BANK_KEY.0 + PREVIOUS_BANK_KEY.1 * 8] = bank_step_key;
+        left_mask[NEXT_BANK_KEY.0 + NEXT_BANK_KEY.1 * 8] = bank_step_key;
+        self.map_loop_selector()
+            .into_iter()
+            .enumerate()
+            .for_each(|(index, value)| right_mask[index + LOOP_START_ROW * 8] = value);
+        self.map_meters()
+            .into_iter()
+            .enumerate()
+            .for_each(|(index, value)| right_mask[index + CHANNEL_METER_ROW * 8] = value);
 
         self.redraw_device(&left_mask, &right_mask);
     }
 
+    /// Whether the recordings or the selected channel's loop, filter type, active bank or the
+    /// samples found in its sample dir have changed since the grid was drawn
+    fn is_drawn_state_stale(&self) -> bool {
+        self.recording_state.channel() != self.drawn_recording_channel
+            || self.recording_state.master() != self.drawn_recording_master
+            || self.loop_steps() != self.drawn_loop_steps
+            || self.active_bank() != self.drawn_active_bank
+            || (self.bank_count(), self.sample_count()) != self.drawn_sample_counts
+            || self.filter_type() != self.drawn_filter_type
+    }
+
     fn redraw_device(&mut self, left_mask: &[u8; 64], right_mask: &[u8; 64]) {
         if let Some(device) = &mut self.device {
             device.map(0, 0, left_mask);
@@ -111,18 +269,102 @@ impl Grid {
         grid_mask
     }
 
+    fn map_filter_type_selector(&self) -> [u8; FilterType::ALL.len()] {
+        let mut grid_mask = [5; FilterType::ALL.len()];
+        if let Some(index) = FilterType::ALL
+            .iter()
+            .position(|filter_type| Some(*filter_type) == self.drawn_filter_type)
+        {
+            grid_mask[index] = 10;
+        }
+        grid_mask
+    }
+
+    /// Loop start row with the selected step lit, and loop length row lit up to the length
+    fn map_loop_selector(&self) -> [u8; LOOP_STEPS * 2] {
+        let mut grid_mask = [2; LOOP_STEPS * 2];
+        let (start_step, length_step) = self.drawn_loop_steps;
+
+        grid_mask[start_step] = 10;
+        grid_mask[LOOP_STEPS..=LOOP_STEPS + length_step].fill(10);
+        grid_mask
+    }
+
+    /// Channel and master rows lit up to the rms level with the peak key brighter, and the
+    /// gain reduction row lit for each step of reduction
+    fn map_meters(&self) -> [u8; METER_STEPS * METER_ROWS] {
+        let mut grid_mask = [0; METER_STEPS * METER_ROWS];
+
+        for (index, level) in self.levels.channels.iter().enumerate() {
+            let row = index * METER_STEPS;
+            map_level(level, &mut grid_mask[row..row + METER_STEPS]);
+        }
+        let master_row = (MASTER_METER_ROW - CHANNEL_METER_ROW) * METER_STEPS;
+        map_level(
+            &self.levels.master,
+            &mut grid_mask[master_row..master_row + METER_STEPS],
+        );
+
+        let reduction_row = (GAIN_REDUCTION_ROW - CHANNEL_METER_ROW) * METER_STEPS;
+        let reduction_steps =
+            ((-self.levels.gain_reduction / GAIN_REDUCTION_STEP_DB) as usize).min(METER_STEPS);
+        grid_mask[reduction_row..reduction_row + reduction_steps].fill(10);
+        grid_mask
+    }
+
+    fn map_record_key(&self) -> u8 {
+        match self.drawn_recording_channel {
+            Some(_) => 15,
+            None => 2,
+        }
+    }
+
+    fn map_master_record_key(&self) -> u8 {
+        match self.drawn_recording_master {
+            true => 15,
+            false => 2,
+        }
+    }
+
+    fn map_bank_key(&self) -> u8 {
+        match self.showing_banks {
+            true => 10,
+            false => 5,
+        }
+    }
+
+    /// Dim when the channel only has one bank to step through
+    fn map_bank_step_key(&self) -> u8 {
+        match self.bank_count() > 1 {
+            true => 5,
+            false => 2,
+        }
+    }
+
+    /// A key per bank with the active bank lit brighter
+    fn map_bank_selector(&self) -> [u8; SAMPLE_GRID] {
+        let mut grid_mask = [0; SAMPLE_GRID];
+        let bank_count = self.bank_count().min(SAMPLE_GRID);
+
+        grid_mask[..bank_count].fill(5);
+        if let Some(button_mask) = grid_mask.get_mut(self.active_bank()) {
+            *button_mask = 10;
+        }
+        grid_mask
+    }
+
     fn map_sample_selector(&self) -> [u8; SAMPLE_GRID] {
         let mut grid_mask = [0; SAMPLE_GRID];
-        // let selected_channel = self.selected_channel().expect("Selected channel exists");
-        // let sample_count = selected_channel.sample_count;
-        let sample_count = 20;
+        let sample_count = self.sample_count();
         let selected_sample = self.selected_sample();
+        let page_offset = self.page() * SAMPLE_GRID;
 
-        for (index, button_mask) in grid_mask.iter_mut().enumerate() {
+        for (key, button_mask) in grid_mask.iter_mut().enumerate() {
+            let index = page_offset + key;
             if index >= sample_count {
                 break;
             }
-            if index == *selected_sample {
+            if index == *selected_sample && self.is_selected_sample_bank_active() {
                 *button_mask = 10;
                 continue;
             }
@@ -131,27 +373,195 @@ impl Grid {
         grid_mask
     }
 
+    /// A dim key for each page in the shown page's block with the shown page brighter, and the
+    /// page up and down keys lit when there is a page to move to
+    fn map_page_row(&self) -> [u8; SAMPLE_GRID_X] {
+        let mut grid_mask = [0; SAMPLE_GRID_X];
+        let page = self.page();
+        let page_count = self.page_count();
+        let block_start = self.page_block_start();
+
+        grid_mask[..(page_count - block_start).min(PAGE_INDICATORS)].fill(2);
+        grid_mask[page - block_start] = 5;
+        grid_mask[PAGE_UP_KEY.0] = match page > 0 {
+            true => 5,
+            false => 2,
+        };
+        grid_mask[PAGE_DOWN_KEY.0] = match page + 1 < page_count {
+            true => 5,
+            false => 2,
+        };
+        grid_mask
+    }
+
     pub fn selected_sample(&self) -> &usize {
+        &self.selection().sample
+    }
+
+    pub fn set_selected_sample(&mut self, selected_sample: usize) {
+        let bank = self.active_bank();
+        let selection = self.selection_mut();
+        selection.sample = selected_sample;
+        selection.bank = bank;
+    }
+
+    fn selection(&self) -> &SampleSelection {
         self.selected_sample_indexes
             .get(self.selected_channel_index)
             .expect("Selected sample index within bounds")
     }
 
-    pub fn set_selected_sample(&mut self, selected_sample: usize) {
-        self.selected_sample_indexes[self.selected_channel_index] = selected_sample;
+    fn selection_mut(&mut self) -> &mut SampleSelection {
+        self.selected_sample_indexes
+            .get_mut(self.selected_channel_index)
+            .expect("Selected sample index within bounds")
+    }
+
+    fn is_selected_sample_bank_active(&self) -> bool {
+        self.selection().bank == self.active_bank()
+    }
+
+    /// Page of the sample selector shown for the selected channel, the last page if samples have
+    /// been removed since it was shown
+    fn page(&self) -> usize {
+        self.selection().page.min(self.page_count() - 1)
+    }
+
+    /// Pages needed to show every sample in the active bank, there is always at least one
+    fn page_count(&self) -> usize {
+        self.sample_count().div_ceil(SAMPLE_GRID).max(1)
+    }
+
+    /// First page of the block of pages the page keys show
+    fn page_block_start(&self) -> usize {
+        let page = self.page();
+        page - page % PAGE_INDICATORS
+    }
+
+    /// Show a page of the sample selector, ignored if the page doesn't exist
+    fn set_page(&mut self, page: usize) {
+        if page < self.page_count() {
+            self.selection_mut().page = page;
+        }
+    }
+
+    /// Selected channel's loop as the nearest start step and length step, loops saved in sample
+    /// files don't have to fall on a step
+    fn loop_steps(&self) -> (usize, usize) {
+        let (start, length) = self
+            .channel_loops
+            .get(self.selected_channel_index)
+            .unwrap_or((0.0, 1.0));
+        let start_step = ((start * LOOP_STEPS as f64).round() as usize).min(LOOP_STEPS - 1);
+        let length_step = ((length * LOOP_STEPS as f64).round() as usize).clamp(1, LOOP_STEPS) - 1;
+        (start_step, length_step)
+    }
+
+    fn filter_type(&self) -> Option<FilterType> {
+        self.channel_filter_types.get(self.selected_channel_index)
+    }
+
+    fn bank_count(&self) -> usize {
+        self.sample_counts.bank_count(self.selected_channel_index)
+    }
+
+    /// Samples in the selected channel's active bank
+    fn sample_count(&self) -> usize {
+        self.sample_counts
+            .sample_count(self.selected_channel_index, self.active_bank())
+    }
+
+    /// Selected channel's active bank as the sample manager has it, banks can also be changed
+    /// over midi
+    fn active_bank(&self) -> usize {
+        self.sample_counts.active_bank(self.selected_channel_index)
     }
 
     pub fn match_action(&mut self, coords: (usize, usize)) -> Option<ControlMessage> {
         match coords {
+            RECORD_KEY => match self.recording_state.channel() {
+                Some(channel_index) => Some(ControlMessage::StopChannelRecording(channel_index)),
+                None => Some(ControlMessage::StartChannelRecording(
+                    self.selected_channel_index,
+                )),
+            },
+            MASTER_RECORD_KEY => match self.recording_state.master() {
+                true => Some(ControlMessage::StopRecording),
+                false => Some(ControlMessage::StartRecording),
+            },
+            TRIGGER_KEY => Some(ControlMessage::TriggerChannel(self.selected_channel_index)),
+            BANK_KEY => {
+                self.showing_banks = !self.showing_banks;
+                None
+            }
+            PREVIOUS_BANK_KEY => {
+                self.selection_mut().page = 0;
+                Some(ControlMessage::PreviousBank(self.selected_channel_index))
+            }
+            NEXT_BANK_KEY => {
+                self.selection_mut().page = 0;
+                Some(ControlMessage::NextBank(self.selected_channel_index))
+            }
+            PAGE_UP_KEY => {
+                if let Some(page) = self.page().checked_sub(1) {
+                    self.set_page(page);
+                }
+                None
+            }
+            PAGE_DOWN_KEY => {
+                self.set_page(self.page() + 1);
+                None
+            }
+            (x, PAGE_ROW) if x < PAGE_INDICATORS => {
+                self.set_page(self.page_block_start() + x);
+                None
+            }
+            (x, y) if self.showing_banks && x < SAMPLE_GRID_X && y < SAMPLE_GRID_Y => {
+                let bank_index = x + SAMPLE_GRID_X * y;
+
+                if bank_index >= self.bank_count() {
+                    return None;
+                }
+                self.selection_mut().page = 0;
+                self.showing_banks = false;
+
+                Some(ControlMessage::SelectBank(
+                    self.selected_channel_index,
+                    bank_index,
+                ))
+            }
+            (x, LOOP_START_ROW) if x >= LOOP_GRID_X => {
+                let step = x - LOOP_GRID_X;
+
+                Some(ControlMessage::SetChannelLoopStart(
+                    self.selected_channel_index,
+                    step as f32 / LOOP_STEPS as f32,
+                ))
+            }
+            (x, LOOP_LENGTH_ROW) if x >= LOOP_GRID_X => {
+                let step = x - LOOP_GRID_X;
+
+                Some(ControlMessage::SetChannelLoopLength(
+                    self.selected_channel_index,
+                    (step + 1) as f32 / LOOP_STEPS as f32,
+                ))
+            }
+            (x, FILTER_TYPE_ROW) if x < FilterType::ALL.len() => {
+                let filter_type = FilterType::ALL[x];
+
+                Some(ControlMessage::SetChannelFilterType(
+                    self.selected_channel_index,
+                    filter_type,
+                ))
+            }
             (x, 7) if x < self.selected_sample_indexes.len() => {
                 self.selected_channel_index = x;
                 None
             }
             (x, y) if x < SAMPLE_GRID_X && y < SAMPLE_GRID_Y => {
-                let sample_file_index = x + SAMPLE_GRID_X * y;
-                let sample_count = 20;
+                let sample_file_index = self.page() * SAMPLE_GRID + x + SAMPLE_GRID_X * y;
 
-                if sample_file_index >= sample_count {
+                if sample_file_index >= self.sample_count() {
                     return None;
                 }
                 self.set_selected_sample(sample_file_index);
@@ -168,3 +578,19 @@ impl Grid {
         }
     }
 }
+
+/// Number of meter keys lit for a level in dBFS
+fn level_steps(db: f32) -> usize {
+    let steps = METER_STEPS as f32 + db / LEVEL_STEP_DB;
+    (steps.max(0.0) as usize).min(METER_STEPS)
+}
+
+fn map_level(level: &Level, row_mask: &mut [u8]) {
+    row_mask[..level_steps(level.rms)].fill(5);
+    if let Some(peak_step) = level_steps(level.peak).checked_sub(1) {
+        row_mask[peak_step] = match level.is_clipping() {
+            true => 15,
+            false => 10,
+        };
+    }
+}
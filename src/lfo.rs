@@ -0,0 +1,136 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use web_audio_api::context::{AudioContext, BaseAudioContext};
+use web_audio_api::node::{
+    AudioNode, AudioScheduledSourceNode, ConstantSourceNode, GainNode, OscillatorNode,
+    OscillatorType,
+};
+
+use crate::settings::{LfoSettings, LfoShape, LfoTarget};
+
+/// Slowest rate in Hz that stepped shapes are scheduled at
+const MIN_STEP_RATE: f32 = 0.01;
+/// Longest the stepping thread sleeps before checking the rate again and whether the lfo is gone
+const STEP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Largest change in value a random walk can take per step
+const RANDOM_WALK_STEP: f32 = 0.25;
+
+enum LfoSource {
+    Oscillator(OscillatorNode),
+    /// Shapes that are scheduled step by step, the rate is shared with the scheduling thread
+    Stepped(Arc<AtomicU32>),
+}
+
+/// Low frequency oscillator that adds a modulation signal between -depth and depth to one of a
+/// channel's params
+pub struct Lfo {
+    source: LfoSource,
+    output: GainNode,
+    channel_index: usize,
+    target: LfoTarget,
+}
+
+impl Lfo {
+    pub fn new(context: &AudioContext, settings: &LfoSettings) -> Self {
+        let output = context.create_gain();
+        output.gain().set_value(settings.depth());
+
+        let source = match settings.shape() {
+            LfoShape::Sine | LfoShape::Triangle => {
+                let oscillator = context.create_oscillator();
+                oscillator.set_type(match settings.shape() {
+                    LfoShape::Triangle => OscillatorType::Triangle,
+                    _ => OscillatorType::Sine,
+                });
+                oscillator.frequency().set_value(settings.rate());
+                oscillator.connect(&output);
+                oscillator.start();
+                LfoSource::Oscillator(oscillator)
+            }
+            LfoShape::SampleAndHold | LfoShape::RandomWalk => {
+                let constant = context.create_constant_source();
+                constant.offset().set_value(0.0);
+                constant.connect(&output);
+                constant.start();
+                let rate = Arc::new(AtomicU32::new(settings.rate().to_bits()));
+                spawn_stepper(constant, settings.shape(), rate.clone());
+                LfoSource::Stepped(rate)
+            }
+        };
+
+        Self {
+            source,
+            output,
+            channel_index: settings.channel(),
+            target: settings.target(),
+        }
+    }
+
+    pub fn output(&self) -> &GainNode {
+        &self.output
+    }
+
+    pub fn target(&self) -> (usize, LfoTarget) {
+        (self.channel_index, self.target)
+    }
+
+    /// Point the lfo at another param, the caller is responsible for reconnecting the output
+    pub fn set_target(&mut self, channel_index: usize, target: LfoTarget) {
+        self.channel_index = channel_index;
+        self.target = target;
+    }
+
+    pub fn set_rate(&self, value: f32) {
+        match &self.source {
+            LfoSource::Oscillator(oscillator) => {
+                oscillator.frequency().set_value(value);
+            }
+            LfoSource::Stepped(rate) => rate.store(value.to_bits(), Ordering::SeqCst),
+        }
+    }
+
+    pub fn set_depth(&self, value: f32) {
+        self.output.gain().set_value(value);
+    }
+}
+
+/// Schedule new values for sample and hold or random walk shapes at the lfo rate, until the lfo
+/// is dropped
+fn spawn_stepper(constant: ConstantSourceNode, shape: LfoShape, rate: Arc<AtomicU32>) {
+    thread::spawn(move || {
+        let mut rng = rand::thread_rng();
+        let mut value: f32 = 0.0;
+        let mut last_step: Option<Instant> = None;
+
+        while Arc::strong_count(&rate) > 1 {
+            let rate = f32::from_bits(rate.load(Ordering::SeqCst)).max(MIN_STEP_RATE);
+            let period = Duration::from_secs_f64(1.0 / rate as f64);
+            // a slow step is waited out in slices, so a faster rate or a dropped lfo is noticed
+            if let Some(remaining) = last_step.and_then(|last| period.checked_sub(last.elapsed())) {
+                thread::sleep(remaining.min(STEP_POLL_INTERVAL));
+                continue;
+            }
+            last_step = Some(Instant::now());
+
+            let period = period.as_secs_f64();
+            let now = constant.context().current_time();
+            let offset = constant.offset();
+
+            match shape {
+                LfoShape::RandomWalk => {
+                    value = (value + rng.gen_range(-RANDOM_WALK_STEP..=RANDOM_WALK_STEP))
+                        .clamp(-1.0, 1.0);
+                    offset.linear_ramp_to_value_at_time(value, now + period);
+                }
+                _ => {
+                    value = rng.gen_range(-1.0..=1.0);
+                    offset.set_value_at_time(value, now);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use web_audio_api::context::{AudioContext, BaseAudioContext};
+use web_audio_api::node::{
+    AudioDestinationNode, AudioNode, DynamicsCompressorNode, GainNode, OverSampleType,
+    WaveShaperNode,
+};
+
+use crate::settings::LimiterSettings;
+
+/// Number of points used to describe the clipping curve
+const CLIP_CURVE_LENGTH: usize = 1024;
+const LIMITER_RATIO: f32 = 20.0;
+const LIMITER_ATTACK: f32 = 0.001;
+const LIMITER_RELEASE: f32 = 0.1;
+
+/// Limits the master output below the threshold, then clips anything that gets past it at the
+/// ceiling
+pub struct Limiter {
+    compressor: Arc<DynamicsCompressorNode>,
+    /// Takes off the makeup gain the compressor always adds
+    _trim: GainNode,
+    clipper: WaveShaperNode,
+    /// Makeup gain in dB added by the compressor
+    makeup: f32,
+}
+
+impl Limiter {
+    pub fn new(
+        context: &AudioContext,
+        destination: &AudioDestinationNode,
+        settings: &LimiterSettings,
+    ) -> Self {
+        let clipper = context.create_wave_shaper();
+        clipper.set_curve(clip_curve(
+            db_to_gain(settings.ceiling()),
+            settings.soft_clip(),
+        ));
+        clipper.set_oversample(OverSampleType::X2);
+        clipper.connect(destination);
+
+        let makeup = makeup_gain(settings.threshold(), LIMITER_RATIO);
+        let trim = context.create_gain();
+        trim.gain().set_value(db_to_gain(-makeup));
+        trim.connect(&clipper);
+
+        let compressor = context.create_dynamics_compressor();
+        compressor.threshold().set_value(settings.threshold());
+        compressor.knee().set_value(0.0);
+        compressor.ratio().set_value(LIMITER_RATIO);
+        compressor.attack().set_value(LIMITER_ATTACK);
+        compressor.release().set_value(LIMITER_RELEASE);
+        compressor.connect(&trim);
+
+        Self {
+            compressor: Arc::new(compressor),
+            _trim: trim,
+            clipper,
+            makeup,
+        }
+    }
+
+    pub fn input(&self) -> &DynamicsCompressorNode {
+        &self.compressor
+    }
+
+    pub fn output(&self) -> &WaveShaperNode {
+        &self.clipper
+    }
+
+    /// Handle for reading the gain reduction from another thread
+    pub fn gain_reduction(&self) -> GainReduction {
+        GainReduction {
+            compressor: self.compressor.clone(),
+            makeup: self.makeup,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GainReduction {
+    compressor: Arc<DynamicsCompressorNode>,
+    makeup: f32,
+}
+
+impl GainReduction {
+    /// Current gain reduction in dB, zero or below. The compressor reports its reduction with the
+    /// makeup gain added, which the limiter takes off again.
+    pub fn read(&self) -> f32 {
+        (self.compressor.reduction() - self.makeup).min(0.0)
+    }
+}
+
+/// Gain in dB the compressor adds to everything: 0.6 of the gain that would bring a full scale
+/// signal back up to full scale, following the web audio spec with a hard knee
+fn makeup_gain(threshold: f32, ratio: f32) -> f32 {
+    let full_range_gain = threshold - threshold / ratio;
+    -0.6 * full_range_gain
+}
+
+/// Transfer curve that is linear for quiet signals and never exceeds the ceiling
+fn clip_curve(ceiling: f32, soft_clip: bool) -> Vec<f32> {
+    (0..CLIP_CURVE_LENGTH)
+        .map(|index| {
+            let input = index as f32 / (CLIP_CURVE_LENGTH - 1) as f32 * 2.0 - 1.0;
+            match soft_clip {
+                true => ceiling * (input / ceiling).tanh(),
+                false => input.clamp(-ceiling, ceiling),
+            }
+        })
+        .collect()
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
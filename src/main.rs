@@ -1,20 +1,47 @@
 use grid::Grid;
 use message::ControlMessage;
+use meter::Levels;
 use midi::Midi;
 use sample_manager::SampleManager;
 use settings::Settings;
-use std::{process, sync::mpsc::channel, time::Duration};
+use std::{env, process, sync::mpsc::channel, time::Duration};
 
 mod audio_graph;
+mod buffer_cache;
+mod effects;
+mod granular;
 mod grid;
+mod lfo;
+mod limiter;
 mod message;
+mod metadata;
+mod meter;
 mod midi;
+mod recorder;
+mod recording;
 mod sample_manager;
+mod sample_watcher;
+mod sampler;
 mod settings;
+mod streaming;
+mod stretch;
+mod watchdog;
+mod wav;
 
 use crate::audio_graph::AudioGraph;
 
 pub const MAX_CHANNEL_COUNT: usize = 4;
+pub const MAX_DELAY_TIME: f64 = 5.0;
+pub const MIN_BPM: f32 = 40.0;
+pub const MAX_BPM: f32 = 240.0;
+/// Furthest a channel can be pitch shifted in semitones
+pub const MAX_PITCH_SHIFT: f32 = 24.0;
+
+/// Print the audio output devices and exit
+const LIST_AUDIO_DEVICES_ARG: &str = "--list-audio-devices";
+
+/// Gain reduction in dB beyond which the limiter is logged as working hard
+const GAIN_REDUCTION_LOG_THRESHOLD: f32 = -6.0;
 
 #[derive(Debug, thiserror::Error)]
 enum Error {
@@ -29,14 +56,39 @@ enum Error {
 }
 
 fn main() -> Result<(), Error> {
+    if env::args().any(|arg| arg == LIST_AUDIO_DEVICES_ARG) {
+        list_audio_devices();
+        return Ok(());
+    }
+
     let settings = Settings::new()?;
     let (control_tx, control_rx) = channel::<ControlMessage>();
-    let (grid, grid_tx) = Grid::new(&settings);
     let sample_manager = SampleManager::new(&settings);
+    let mut audio_graph = AudioGraph::new(&settings, control_tx.clone())?;
+    let (grid, grid_tx) = Grid::new(
+        &settings,
+        sample_manager.sample_counts(),
+        audio_graph.channel_loops(),
+        audio_graph.channel_filter_types(),
+        audio_graph.recording_state(),
+    );
     let mut midi = Midi::start(control_tx.clone(), settings.clone())?;
-    let mut audio_graph = AudioGraph::new(&settings);
 
     grid.start(control_tx.clone());
+    sample_manager.start_watcher(grid_tx.clone());
+    sample_manager.start_metadata_scan();
+
+    audio_graph.start_watchdog();
+    audio_graph.preload(sample_manager.preload_paths());
+
+    let mut metering = audio_graph.metering();
+    let meter_grid_tx = grid_tx.clone();
+    metering.subscribe(move |levels| {
+        let _ = meter_grid_tx.send(grid::GridMessage::Levels(levels.clone()));
+    });
+    metering.subscribe(log_levels);
+    metering.start();
+
     midi.init_values(&settings)?;
 
     ctrlc::set_handler(move || {
@@ -44,7 +96,8 @@ fn main() -> Result<(), Error> {
         control_tx.send(ControlMessage::MuteAll).unwrap();
         // wait for grid to clear and audio graph to fade
         std::thread::sleep(Duration::from_millis(250));
-        process::exit(130);
+        // the control loop exits once the master recording has been finalised
+        control_tx.send(ControlMessage::Quit).unwrap();
     })
     .expect("Error setting Ctrl-C handler");
 
@@ -52,12 +105,36 @@ fn main() -> Result<(), Error> {
         let sample_file = sample_manager
             .get_path_for_sample(channel_index, 0)
             .expect("Found default file in sample directory for channel");
-        audio_graph.load_and_play_for_channel(channel_index, &sample_file);
+        if let Err(err) = audio_graph.load_and_play_for_channel(channel_index, &sample_file) {
+            println!("failed to load {:?}: {}", sample_file, err);
+        }
     }
 
     for control_message in control_rx {
+        let quit = matches!(control_message, ControlMessage::Quit);
         message::process_message(control_message, &mut audio_graph, &sample_manager)?;
+        if quit {
+            process::exit(130);
+        }
     }
 
     Ok(())
 }
+
+fn list_audio_devices() {
+    println!("Audio output devices (sink_id: label):");
+    for (sink_id, label) in audio_graph::output_devices() {
+        println!("{}: {}", sink_id, label);
+    }
+}
+
+fn log_levels(levels: &Levels) {
+    if levels.gain_reduction < GAIN_REDUCTION_LOG_THRESHOLD {
+        println!("limiter gain reduction: {:.1} dB", levels.gain_reduction);
+    }
+    for (channel_index, level) in levels.channels.iter().enumerate() {
+        if level.is_clipping() {
+            println!("channel {} clipping: {:.1} dBFS", channel_index, level.peak);
+        }
+    }
+}
@@ -1,23 +1,115 @@
+use std::mem::{self, Discriminant};
 use std::println;
 
-use crate::{audio_graph::AudioGraph, sample_manager::SampleManager};
+use crate::{
+    audio_graph::{self, AudioGraph},
+    sample_manager::SampleManager,
+    settings::{FilterType, LfoTarget, PlaybackMode},
+};
 
 type AudioChannel = usize;
+type LfoIndex = usize;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("audio channel does not exist")]
     MissingAudioChannel,
+    #[error("lfo does not exist")]
+    MissingLfo,
+    #[error("audio channel does not use the granular engine")]
+    MissingGranulator,
+    #[error("failed to control audio graph")]
+    AudioGraph(#[from] audio_graph::Error),
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum ControlMessage {
     MuteAll,
+    NextBank(AudioChannel),
+    PreviousBank(AudioChannel),
+    /// Finish any master recording, the control loop exits once it has been processed
+    Quit,
+    RebuildAudioGraph,
+    SelectBank(AudioChannel, usize),
+    SetBpm(f32),
+    SetChannelDelaySend(AudioChannel, f32),
     SetChannelFilterFrequency(AudioChannel, f32),
     SetChannelFilterQ(AudioChannel, f32),
+    SetChannelFilterType(AudioChannel, FilterType),
+    SetChannelGrainDensity(AudioChannel, f32),
+    SetChannelGrainPitchJitter(AudioChannel, f32),
+    SetChannelGrainPosition(AudioChannel, f32),
+    SetChannelGrainSize(AudioChannel, f32),
+    SetChannelGrainSpray(AudioChannel, f32),
+    SetChannelLoopLength(AudioChannel, f32),
+    SetChannelLoopStart(AudioChannel, f32),
+    SetChannelPan(AudioChannel, f32),
+    SetChannelPitch(AudioChannel, f32),
+    SetChannelPlaybackMode(AudioChannel, PlaybackMode),
     SetChannelRate(AudioChannel, f32),
+    SetChannelReverbSend(AudioChannel, f32),
     SetChannelSampleFile(AudioChannel, usize),
     SetChannelVolume(AudioChannel, f32),
+    SetDelayReturn(f32),
+    SetLfoDepth(LfoIndex, f32),
+    SetLfoRate(LfoIndex, f32),
+    SetLfoTarget(LfoIndex, AudioChannel, LfoTarget),
+    SetReverbReturn(f32),
+    StartChannelRecording(AudioChannel),
+    StartRecording,
+    StopChannelRecording(AudioChannel),
+    StopFadedStreams,
+    StopRecording,
+    StretchFinished,
+    TriggerChannel(AudioChannel),
+}
+
+impl ControlMessage {
+    /// Identifies the param a message sets, `None` for messages that don't set a param
+    pub fn param_key(&self) -> Option<(Discriminant<Self>, usize)> {
+        let index = match *self {
+            ControlMessage::SetChannelDelaySend(index, _)
+            | ControlMessage::SetChannelFilterFrequency(index, _)
+            | ControlMessage::SetChannelFilterQ(index, _)
+            | ControlMessage::SetChannelFilterType(index, _)
+            | ControlMessage::SetChannelGrainDensity(index, _)
+            | ControlMessage::SetChannelGrainPitchJitter(index, _)
+            | ControlMessage::SetChannelGrainPosition(index, _)
+            | ControlMessage::SetChannelGrainSize(index, _)
+            | ControlMessage::SetChannelGrainSpray(index, _)
+            | ControlMessage::SetChannelLoopLength(index, _)
+            | ControlMessage::SetChannelLoopStart(index, _)
+            | ControlMessage::SetChannelPan(index, _)
+            | ControlMessage::SetChannelPitch(index, _)
+            | ControlMessage::SetChannelPlaybackMode(index, _)
+            | ControlMessage::SetChannelRate(index, _)
+            | ControlMessage::SetChannelReverbSend(index, _)
+            | ControlMessage::SetChannelVolume(index, _)
+            | ControlMessage::SetLfoDepth(index, _)
+            | ControlMessage::SetLfoRate(index, _)
+            | ControlMessage::SetLfoTarget(index, _, _) => index,
+            ControlMessage::SetBpm(_)
+            | ControlMessage::SetDelayReturn(_)
+            | ControlMessage::SetReverbReturn(_) => 0,
+            // samples are reloaded from the file the channel is playing
+            ControlMessage::SetChannelSampleFile(..)
+            | ControlMessage::MuteAll
+            | ControlMessage::NextBank(_)
+            | ControlMessage::PreviousBank(_)
+            | ControlMessage::Quit
+            | ControlMessage::RebuildAudioGraph
+            | ControlMessage::SelectBank(..)
+            | ControlMessage::StartChannelRecording(_)
+            | ControlMessage::StartRecording
+            | ControlMessage::StopChannelRecording(_)
+            | ControlMessage::StopFadedStreams
+            | ControlMessage::StopRecording
+            | ControlMessage::StretchFinished
+            | ControlMessage::TriggerChannel(_) => return None,
+        };
+
+        Some((mem::discriminant(self), index))
+    }
 }
 
 pub fn process_message(
@@ -26,13 +118,70 @@ pub fn process_message(
     sample_manager: &SampleManager,
 ) -> Result<(), Error> {
     println!("Message: {:?}", msg);
+    audio_graph.remember_param(msg);
 
     match msg {
         ControlMessage::MuteAll => audio_graph.mute_all(),
-        ControlMessage::SetChannelFilterFrequency(channel_index, freq) => {
+        ControlMessage::NextBank(channel_index) => {
+            let bank = sample_manager
+                .next_bank(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            println!("Channel {} bank: {:?}", channel_index, bank);
+            audio_graph.preload(sample_manager.bank_paths(channel_index));
+        }
+        ControlMessage::PreviousBank(channel_index) => {
+            let bank = sample_manager
+                .previous_bank(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            println!("Channel {} bank: {:?}", channel_index, bank);
+            audio_graph.preload(sample_manager.bank_paths(channel_index));
+        }
+        ControlMessage::RebuildAudioGraph => {
+            // the watchdog asks again until it has been handed a new context
+            if !audio_graph.needs_rebuild() {
+                return Ok(());
+            }
+            if let Err(err) = audio_graph.rebuild() {
+                println!("failed to rebuild audio graph: {}", err);
+            }
+            for param in audio_graph.param_history() {
+                // the rebuild carried the loop controls over, replaying them would replace loop
+                // points read from the reloaded samples
+                if matches!(
+                    param,
+                    ControlMessage::SetChannelLoopStart(..)
+                        | ControlMessage::SetChannelLoopLength(..)
+                ) {
+                    continue;
+                }
+                if let Err(err) = process_message(param, audio_graph, sample_manager) {
+                    println!("failed to restore {:?}: {}", param, err);
+                }
+            }
+        }
+        ControlMessage::SelectBank(channel_index, bank_index) => {
+            sample_manager
+                .get_dir_for_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            // a bank key can be pressed for a bank that was just removed
+            let Some(bank) = sample_manager.select_bank(channel_index, bank_index) else {
+                println!("Channel {} has no bank {}", channel_index, bank_index);
+                return Ok(());
+            };
+            println!("Channel {} bank: {:?}", channel_index, bank);
+            audio_graph.preload(sample_manager.bank_paths(channel_index));
+        }
+        ControlMessage::SetBpm(bpm) => audio_graph.set_bpm(bpm),
+        ControlMessage::SetChannelDelaySend(channel_index, level) => {
             let channel = audio_graph
                 .get_channel(channel_index)
                 .ok_or(Error::MissingAudioChannel)?;
+            channel.set_delay_send(level);
+        }
+        ControlMessage::SetChannelFilterFrequency(channel_index, freq) => {
+            let channel = audio_graph
+                .get_channel_mut(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
             channel.set_filter_frequency(freq);
         }
         ControlMessage::SetChannelFilterQ(channel_index, q) => {
@@ -41,17 +190,98 @@ pub fn process_message(
                 .ok_or(Error::MissingAudioChannel)?;
             channel.set_filter_q(q);
         }
-        ControlMessage::SetChannelRate(channel_index, rate) => {
+        ControlMessage::SetChannelFilterType(channel_index, filter_type) => {
+            audio_graph
+                .set_filter_type(channel_index, filter_type)
+                .ok_or(Error::MissingAudioChannel)?;
+        }
+        ControlMessage::SetChannelGrainDensity(channel_index, value) => {
+            let channel = audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            let granulator = channel.granulator().ok_or(Error::MissingGranulator)?;
+            granulator.set_density(value);
+        }
+        ControlMessage::SetChannelGrainPitchJitter(channel_index, value) => {
+            let channel = audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            let granulator = channel.granulator().ok_or(Error::MissingGranulator)?;
+            granulator.set_pitch_jitter(value);
+        }
+        ControlMessage::SetChannelGrainPosition(channel_index, value) => {
+            let channel = audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            let granulator = channel.granulator().ok_or(Error::MissingGranulator)?;
+            granulator.set_position(value);
+        }
+        ControlMessage::SetChannelGrainSize(channel_index, value) => {
+            let channel = audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            let granulator = channel.granulator().ok_or(Error::MissingGranulator)?;
+            granulator.set_size(value);
+        }
+        ControlMessage::SetChannelGrainSpray(channel_index, value) => {
             let channel = audio_graph
                 .get_channel(channel_index)
                 .ok_or(Error::MissingAudioChannel)?;
+            let granulator = channel.granulator().ok_or(Error::MissingGranulator)?;
+            granulator.set_spray(value);
+        }
+        ControlMessage::SetChannelLoopLength(channel_index, length) => {
+            audio_graph
+                .set_loop_length(channel_index, length)
+                .ok_or(Error::MissingAudioChannel)?;
+        }
+        ControlMessage::SetChannelLoopStart(channel_index, start) => {
+            audio_graph
+                .set_loop_start(channel_index, start)
+                .ok_or(Error::MissingAudioChannel)?;
+        }
+        ControlMessage::SetChannelPan(channel_index, pan) => {
+            let channel = audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            channel.set_pan(pan);
+        }
+        ControlMessage::SetChannelPitch(channel_index, semitones) => {
+            let channel = audio_graph
+                .get_channel_mut(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            channel.set_pitch(semitones);
+        }
+        ControlMessage::SetChannelPlaybackMode(channel_index, playback_mode) => {
+            audio_graph
+                .set_playback_mode(channel_index, playback_mode)
+                .ok_or(Error::MissingAudioChannel)?;
+        }
+        ControlMessage::SetChannelRate(channel_index, rate) => {
+            let channel = audio_graph
+                .get_channel_mut(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
             channel.set_rate(rate);
         }
+        ControlMessage::SetChannelReverbSend(channel_index, level) => {
+            let channel = audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            channel.set_reverb_send(level);
+        }
         ControlMessage::SetChannelSampleFile(channel_index, sample_index) => {
             let sample_file = sample_manager.get_path_for_sample(channel_index, sample_index);
 
             if let Some(sample_file) = sample_file {
-                audio_graph.load_and_play_for_channel(channel_index, &sample_file);
+                if let Some(metadata) =
+                    sample_manager.get_metadata_for_sample(channel_index, sample_index)
+                {
+                    println!("Sample: {:?} {}", sample_file, metadata);
+                }
+                if let Err(err) = audio_graph.load_and_play_for_channel(channel_index, &sample_file)
+                {
+                    println!("failed to load {:?}: {}", sample_file, err);
+                }
             };
         }
         ControlMessage::SetChannelVolume(channel_index, level) => {
@@ -61,6 +291,61 @@ pub fn process_message(
                 .ok_or(Error::MissingAudioChannel)?;
             channel.set_volume(level);
         }
+        ControlMessage::SetDelayReturn(level) => audio_graph.set_delay_return(level),
+        ControlMessage::SetLfoDepth(lfo_index, depth) => {
+            let lfo = audio_graph.get_lfo(lfo_index).ok_or(Error::MissingLfo)?;
+            lfo.set_depth(depth);
+        }
+        ControlMessage::SetLfoRate(lfo_index, rate) => {
+            let lfo = audio_graph.get_lfo(lfo_index).ok_or(Error::MissingLfo)?;
+            lfo.set_rate(rate);
+        }
+        ControlMessage::SetLfoTarget(lfo_index, channel_index, target) => {
+            audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            audio_graph
+                .set_lfo_target(lfo_index, channel_index, target)
+                .ok_or(Error::MissingLfo)?;
+        }
+        ControlMessage::SetReverbReturn(level) => audio_graph.set_reverb_return(level),
+        ControlMessage::StartChannelRecording(channel_index) => {
+            audio_graph
+                .get_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            audio_graph.start_recording(channel_index);
+        }
+        ControlMessage::StartRecording => {
+            if let Err(err) = audio_graph.start_master_recording() {
+                println!("failed to start master recording: {}", err);
+            }
+        }
+        ControlMessage::StopChannelRecording(channel_index) => {
+            let sample_dir = sample_manager
+                .get_dir_for_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+            match audio_graph.stop_recording(channel_index, &sample_dir) {
+                // the recording is saved as a new sample
+                Ok(()) => sample_manager.refresh(),
+                Err(err) => println!(
+                    "failed to save channel {} recording: {}",
+                    channel_index, err
+                ),
+            }
+        }
+        ControlMessage::StopFadedStreams => audio_graph.stop_faded_streams(),
+        // stopping waits for the recording to be written and finalised
+        ControlMessage::Quit | ControlMessage::StopRecording => {
+            if let Err(err) = audio_graph.stop_master_recording() {
+                println!("failed to stop master recording: {}", err);
+            }
+        }
+        ControlMessage::StretchFinished => audio_graph.finish_stretches(),
+        ControlMessage::TriggerChannel(channel_index) => {
+            audio_graph
+                .trigger_channel(channel_index)
+                .ok_or(Error::MissingAudioChannel)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use web_audio_api::media::MediaDecoder;
+
+use crate::meter::gain_to_db;
+use crate::{wav, MAX_BPM, MIN_BPM};
+
+/// Sidecar file in each bank directory holding the metadata of the samples in it
+pub const METADATA_FILE: &str = ".biome-metadata.json";
+const PITCH_CLASS_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+/// Semitones above C of the natural notes A to G
+const NATURAL_PITCH_CLASSES: [u8; 7] = [9, 11, 0, 2, 4, 5, 7];
+const KEY_MODES: [&str; 6] = ["", "m", "min", "minor", "maj", "major"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read sample file")]
+    ReadFile(#[from] io::Error),
+    #[error("failed to decode sample file")]
+    Decode(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("failed to parse metadata file")]
+    ParseMetadata(#[from] serde_json::Error),
+}
+
+/// What is known about a sample file, measured once and kept in its directory's metadata file
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SampleMetadata {
+    /// Length in seconds
+    pub duration: f64,
+    pub sample_rate: f32,
+    pub channels: usize,
+    /// Peak level of the whole file in dBFS
+    pub peak: f32,
+    /// Rms level of the whole file in dBFS
+    pub rms: f32,
+    pub bpm: Option<f32>,
+    /// Pitch class of the root key, 0 for C up to 11 for B
+    pub root_key: Option<u8>,
+    /// Size in bytes and modification time in milliseconds of the file when it was measured, it is
+    /// measured again when either changes
+    size: u64,
+    modified: u64,
+}
+
+impl SampleMetadata {
+    /// Decode a sample file to measure it, reading the tempo and root key from the wav acid and
+    /// smpl chunks or the file name
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let file = fs::metadata(path)?;
+        let decoder = MediaDecoder::try_new(File::open(path)?)?;
+
+        let mut sample_rate = 0.0;
+        let mut channels = 0;
+        let mut frames = 0;
+        let mut peak = 0.0_f32;
+        let mut sum_of_squares = 0.0_f64;
+        // decoded a packet at a time so long files don't have to fit in memory
+        for buffer in decoder {
+            let buffer = buffer?;
+            sample_rate = buffer.sample_rate();
+            channels = buffer.number_of_channels();
+            frames += buffer.length();
+            for channel in 0..channels {
+                for sample in buffer.get_channel_data(channel) {
+                    peak = peak.max(sample.abs());
+                    sum_of_squares += (sample * sample) as f64;
+                }
+            }
+        }
+        let rms = (sum_of_squares / (frames * channels).max(1) as f64).sqrt() as f32;
+        let duration = match sample_rate > 0.0 {
+            true => frames as f64 / sample_rate as f64,
+            false => 0.0,
+        };
+
+        let chunks = wav::read_chunks(path).unwrap_or_default();
+        let name = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+        // unity notes default to middle C, so they are only used when nothing else names a key
+        let root_key = wav::acid_root_note(&chunks)
+            .map(|note| note % 12)
+            .or_else(|| key_from_name(name))
+            .or_else(|| wav::smpl_unity_note(&chunks).map(|note| note % 12));
+
+        Ok(Self {
+            duration,
+            sample_rate,
+            channels,
+            peak: gain_to_db(peak),
+            rms: gain_to_db(rms),
+            bpm: wav::acid_tempo(&chunks).or_else(|| bpm_from_name(name)),
+            root_key,
+            size: file.len(),
+            modified: modified_millis(&file),
+        })
+    }
+
+    fn is_current(&self, file: &fs::Metadata) -> bool {
+        self.size == file.len() && self.modified == modified_millis(file)
+    }
+}
+
+impl fmt::Display for SampleMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}s {}Hz {}ch peak {:.1}dB rms {:.1}dB",
+            self.duration, self.sample_rate, self.channels, self.peak, self.rms
+        )?;
+        if let Some(bpm) = self.bpm {
+            write!(f, " {}bpm", bpm)?;
+        }
+        if let Some(root_key) = self.root_key {
+            write!(f, " key {}", PITCH_CLASS_NAMES[root_key as usize])?;
+        }
+        Ok(())
+    }
+}
+
+/// Metadata for the samples in a directory, reusing the records in its metadata file that are
+/// still current and measuring the rest. The file is rewritten when any record changes.
+pub fn read_dir_metadata(dir: &Path, entries: &[PathBuf]) -> HashMap<PathBuf, SampleMetadata> {
+    let metadata_path = dir.join(METADATA_FILE);
+    let mut stored = match read_metadata_file(&metadata_path) {
+        Ok(stored) => stored,
+        Err(Error::ReadFile(err)) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(err) => {
+            println!("ignoring metadata file {:?}: {}", metadata_path, err);
+            BTreeMap::new()
+        }
+    };
+
+    let mut changed = false;
+    let mut records = BTreeMap::new();
+    for path in entries {
+        let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+            continue;
+        };
+        let Ok(file) = fs::metadata(path) else {
+            continue;
+        };
+
+        let record = match stored.remove(name) {
+            Some(record) if record.is_current(&file) => record,
+            _ => {
+                changed = true;
+                match SampleMetadata::read(path) {
+                    Ok(record) => record,
+                    Err(err) => {
+                        println!("failed to read metadata of {:?}: {}", path, err);
+                        continue;
+                    }
+                }
+            }
+        };
+        records.insert(name.to_string(), record);
+    }
+    // anything left over belongs to files that have been removed
+    changed |= !stored.is_empty();
+
+    if changed {
+        if let Err(err) = write_metadata_file(&metadata_path, &records) {
+            println!("failed to write metadata file {:?}: {}", metadata_path, err);
+        }
+    }
+
+    records
+        .into_iter()
+        .map(|(name, record)| (dir.join(name), record))
+        .collect()
+}
+
+fn read_metadata_file(path: &Path) -> Result<BTreeMap<String, SampleMetadata>, Error> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+fn write_metadata_file(
+    path: &Path,
+    records: &BTreeMap<String, SampleMetadata>,
+) -> Result<(), Error> {
+    fs::write(path, serde_json::to_string_pretty(records)?)?;
+    Ok(())
+}
+
+fn modified_millis(file: &fs::Metadata) -> u64 {
+    file.modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Tempo from a name that labels it, like "loop_120bpm", "loop 120 BPM" or "bpm_120_loop". Bare
+/// numbers aren't read as tempos, they are as likely to be take or version numbers.
+fn bpm_from_name(name: &str) -> Option<f32> {
+    let tokens = name_tokens(name);
+    let parse = |number: &str| {
+        number
+            .parse()
+            .ok()
+            .filter(|bpm| (MIN_BPM..=MAX_BPM).contains(bpm))
+    };
+
+    tokens.iter().enumerate().find_map(|(index, token)| {
+        let token = token.to_ascii_lowercase();
+        match (token.strip_suffix("bpm"), token.strip_prefix("bpm")) {
+            // a bpm label on its own goes with the number before or after it
+            (Some(""), _) => [index.checked_sub(1), Some(index + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|neighbour| tokens.get(neighbour))
+                .find_map(|neighbour| parse(neighbour)),
+            (Some(number), _) | (_, Some(number)) => parse(number),
+            (None, None) => None,
+        }
+    })
+}
+
+/// Root key from a name like "pad_Am", "bass F#" or "lead_Bbmaj"
+fn key_from_name(name: &str) -> Option<u8> {
+    name_tokens(name).into_iter().find_map(|token| {
+        let mut chars = token.chars();
+        let natural = chars.next().filter(|note| ('A'..='G').contains(note))?;
+        let pitch_class = NATURAL_PITCH_CLASSES[(natural as u8 - b'A') as usize];
+
+        let rest = chars.as_str();
+        let (pitch_class, mode) = if let Some(mode) = rest.strip_prefix('#') {
+            ((pitch_class + 1) % 12, mode)
+        } else if let Some(mode) = rest.strip_prefix('b') {
+            ((pitch_class + 11) % 12, mode)
+        } else {
+            (pitch_class, rest)
+        };
+
+        KEY_MODES
+            .iter()
+            .any(|key_mode| key_mode.eq_ignore_ascii_case(mode))
+            .then_some(pitch_class)
+    })
+}
+
+fn name_tokens(name: &str) -> Vec<&str> {
+    name.split(|c: char| !c.is_alphanumeric() && c != '#' && c != '.')
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bpm_from_labelled_names() {
+        assert_eq!(bpm_from_name("loop_120bpm"), Some(120.0));
+        assert_eq!(bpm_from_name("loop 120 BPM"), Some(120.0));
+        assert_eq!(bpm_from_name("bpm_95_break"), Some(95.0));
+        assert_eq!(bpm_from_name("BPM87 pad"), Some(87.0));
+        assert_eq!(bpm_from_name("take_2_92.5bpm_Am"), Some(92.5));
+    }
+
+    #[test]
+    fn bpm_ignores_unlabelled_and_out_of_range_numbers() {
+        assert_eq!(bpm_from_name("kick_120"), None);
+        assert_eq!(bpm_from_name("field recording 2019"), None);
+        assert_eq!(bpm_from_name("loop_900bpm"), None);
+        assert_eq!(bpm_from_name("bpm_loop"), None);
+    }
+
+    #[test]
+    fn key_from_names() {
+        assert_eq!(key_from_name("pad_Am"), Some(9));
+        assert_eq!(key_from_name("bass F#"), Some(6));
+        assert_eq!(key_from_name("lead_Bbmaj"), Some(10));
+        assert_eq!(key_from_name("chord Cminor 120bpm"), Some(0));
+        assert_eq!(key_from_name("Eb"), Some(3));
+    }
+
+    #[test]
+    fn key_ignores_words_starting_with_a_note() {
+        assert_eq!(key_from_name("Break_Drums"), None);
+        assert_eq!(key_from_name("ambient_bed"), None);
+    }
+}
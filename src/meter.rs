@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use web_audio_api::context::{AudioContext, BaseAudioContext};
+use web_audio_api::node::{AnalyserNode, AudioNode};
+
+use crate::limiter::GainReduction;
+
+/// How often levels are measured and published
+pub const METER_INTERVAL: Duration = Duration::from_millis(50);
+/// Level reported for silence
+pub const MIN_LEVEL_DB: f32 = -100.0;
+/// Samples read from each analyser, a little under the meter interval at 44.1kHz
+const ANALYSER_FFT_SIZE: usize = 2048;
+
+/// Peak and rms level in dBFS
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Level {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            peak: MIN_LEVEL_DB,
+            rms: MIN_LEVEL_DB,
+        }
+    }
+}
+
+impl Level {
+    fn measure(samples: &[f32]) -> Self {
+        let peak = samples.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+        let sum_of_squares: f32 = samples.iter().map(|s| s * s).sum();
+        let rms = (sum_of_squares / samples.len().max(1) as f32).sqrt();
+
+        Self {
+            peak: gain_to_db(peak),
+            rms: gain_to_db(rms),
+        }
+    }
+
+    pub fn is_clipping(&self) -> bool {
+        self.peak >= 0.0
+    }
+}
+
+/// One set of readings from the metering service
+#[derive(Clone, Debug, Default)]
+pub struct Levels {
+    pub master: Level,
+    pub channels: Vec<Level>,
+    /// Limiter gain reduction in dB, zero or below
+    pub gain_reduction: f32,
+}
+
+/// Analyser connected alongside a node so its output can be measured from another thread
+#[derive(Clone)]
+pub struct Tap(Arc<AnalyserNode>);
+
+impl Tap {
+    pub fn new(context: &AudioContext, source: &dyn AudioNode) -> Self {
+        let analyser = context.create_analyser();
+        analyser.set_fft_size(ANALYSER_FFT_SIZE);
+        source.connect(&analyser);
+
+        Self(Arc::new(analyser))
+    }
+
+    fn read(&self, buffer: &mut [f32]) -> Level {
+        self.0.get_float_time_domain_data(buffer);
+        Level::measure(buffer)
+    }
+}
+
+/// Everything the metering service reads, swapped out when the audio graph is rebuilt
+#[derive(Clone)]
+pub struct MeterTaps {
+    pub master: Tap,
+    pub channels: Vec<Tap>,
+    pub gain_reduction: GainReduction,
+}
+
+impl MeterTaps {
+    fn read(&self, buffer: &mut [f32]) -> Levels {
+        Levels {
+            master: self.master.read(buffer),
+            channels: self.channels.iter().map(|tap| tap.read(buffer)).collect(),
+            gain_reduction: self.gain_reduction.read(),
+        }
+    }
+}
+
+type Subscriber = Box<dyn Fn(&Levels) + Send>;
+
+/// Measures every tap at a fixed rate and hands the readings to each subscriber
+pub struct Metering {
+    taps: Arc<Mutex<MeterTaps>>,
+    subscribers: Vec<Subscriber>,
+}
+
+impl Metering {
+    pub fn new(taps: Arc<Mutex<MeterTaps>>) -> Self {
+        Self {
+            taps,
+            subscribers: vec![],
+        }
+    }
+
+    /// Called from the metering thread with every new set of readings
+    pub fn subscribe(&mut self, subscriber: impl Fn(&Levels) + Send + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    pub fn start(self) {
+        thread::spawn(move || {
+            let mut buffer = vec![0.0; ANALYSER_FFT_SIZE];
+
+            loop {
+                let levels = self.taps.lock().expect("Meter taps lock").read(&mut buffer);
+
+                for subscriber in &self.subscribers {
+                    subscriber(&levels);
+                }
+
+                thread::sleep(METER_INTERVAL);
+            }
+        });
+    }
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    (20.0 * gain.log10()).max(MIN_LEVEL_DB)
+}
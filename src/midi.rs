@@ -1,12 +1,16 @@
-use midi_control::{ControlEvent, MidiMessage, MidiMessageSend};
+use midi_control::{ControlEvent, KeyEvent, MidiMessage, MidiMessageSend};
 use midir::{
     self, ConnectError, InitError, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection,
 };
 use std::sync::mpsc;
 
 use crate::{
+    granular::{
+        MAX_GRAIN_DENSITY, MAX_GRAIN_SIZE, MAX_PITCH_JITTER, MIN_GRAIN_DENSITY, MIN_GRAIN_SIZE,
+    },
     message::ControlMessage,
-    settings::{ControlParam, Settings},
+    settings::{ControlParam, FilterType, LfoTarget, PlaybackMode, Settings},
+    MAX_BPM, MIN_BPM,
 };
 
 // https://github.com/mmckegg/rust-loop-drop/blob/master/src/midi_connection.rs
@@ -14,6 +18,11 @@ use crate::{
 /// String to look for when enumerating the MIDI devices
 const CLIENT_NAME: &str = "biome";
 
+/// Fastest lfo rate in Hz that can be set from a midi control
+const MAX_LFO_RATE: f32 = 10.0;
+/// Semitones either side of the original pitch that can be set from a midi control
+const MIDI_PITCH_RANGE: f32 = 12.0;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("failed to find midi input device")]
@@ -30,6 +39,12 @@ pub enum Error {
     EchoValue(#[from] midir::SendError),
     #[error("midi value is not assigned to a control type")]
     MissingControlType,
+    #[error("midi value is assigned to a channel that does not exist")]
+    MissingAudioChannel,
+    #[error("midi value is assigned to an lfo that does not exist")]
+    MissingLfo,
+    #[error("midi note is not assigned to a channel")]
+    MissingTriggerNote,
     #[error("failed to transmit control message")]
     TransmitControlMessage(#[from] mpsc::SendError<ControlMessage>),
 }
@@ -78,6 +93,19 @@ impl Midi {
                                 }
                             }
                         }
+                        // some devices send note on with no velocity instead of note off
+                        MidiMessage::NoteOn(channel, event)
+                            if channel == settings.midi_channel() && event.value > 0 =>
+                        {
+                            match parse_note_event(event, settings) {
+                                Ok(ctrl_msg) => {
+                                    tx.send(ctrl_msg).expect("Transmitted control message");
+                                }
+                                Err(error) => {
+                                    eprintln!("couldn't process note message {:?}", error);
+                                }
+                            }
+                        }
                         message => {
                             eprintln!("unsupported midi message {:?}", message);
                         }
@@ -129,6 +157,14 @@ where
     device_port
 }
 
+/// Note on events retrigger the channel assigned to the note
+fn parse_note_event(event: KeyEvent, settings: &Settings) -> Result<ControlMessage, Error> {
+    settings
+        .channel_from_trigger_note(event.key)
+        .map(ControlMessage::TriggerChannel)
+        .ok_or(Error::MissingTriggerNote)
+}
+
 fn parse_control_event(event: ControlEvent, settings: &Settings) -> Result<ControlMessage, Error> {
     println!("ControlChange event: {:?}", event);
 
@@ -137,6 +173,16 @@ fn parse_control_event(event: ControlEvent, settings: &Settings) -> Result<Contr
         .ok_or(Error::MissingControlType)?;
 
     match control_type {
+        ControlParam::Bpm => Ok(ControlMessage::SetBpm(
+            midi_to_range(event.value, MIN_BPM, MAX_BPM).round(),
+        )),
+        ControlParam::DelayReturn => {
+            Ok(ControlMessage::SetDelayReturn(midi_to_percent(event.value)))
+        }
+        ControlParam::DelaySend => Ok(ControlMessage::SetChannelDelaySend(
+            audio_channel,
+            midi_to_percent(event.value),
+        )),
         ControlParam::FilterFrequency => Ok(ControlMessage::SetChannelFilterFrequency(
             audio_channel,
             midi_to_freq(event.value),
@@ -145,7 +191,97 @@ fn parse_control_event(event: ControlEvent, settings: &Settings) -> Result<Contr
             audio_channel,
             midi_to_percent(event.value),
         )),
-        ControlParam::Rate => Ok(ControlMessage::SetChannelRate(
+        ControlParam::FilterType => Ok(ControlMessage::SetChannelFilterType(
+            audio_channel,
+            midi_to_filter_type(event.value),
+        )),
+        ControlParam::GrainDensity => Ok(ControlMessage::SetChannelGrainDensity(
+            audio_channel,
+            midi_to_range(event.value, MIN_GRAIN_DENSITY, MAX_GRAIN_DENSITY),
+        )),
+        ControlParam::GrainPitchJitter => Ok(ControlMessage::SetChannelGrainPitchJitter(
+            audio_channel,
+            midi_to_range(event.value, 0.0, MAX_PITCH_JITTER),
+        )),
+        ControlParam::GrainPosition => Ok(ControlMessage::SetChannelGrainPosition(
+            audio_channel,
+            midi_to_range(event.value, 0.0, 1.0),
+        )),
+        ControlParam::GrainSize => Ok(ControlMessage::SetChannelGrainSize(
+            audio_channel,
+            midi_to_range(event.value, MIN_GRAIN_SIZE, MAX_GRAIN_SIZE),
+        )),
+        ControlParam::GrainSpray => Ok(ControlMessage::SetChannelGrainSpray(
+            audio_channel,
+            midi_to_range(event.value, 0.0, 1.0),
+        )),
+        ControlParam::LfoDepth => {
+            let lfo_index = settings
+                .lfo_index_from_midi_event(event.control)
+                .ok_or(Error::MissingLfo)?;
+            // the depth in settings is the most the midi control can apply
+            let max_depth = settings.lfos()[lfo_index].depth();
+            Ok(ControlMessage::SetLfoDepth(
+                lfo_index,
+                midi_to_range(event.value, 0.0, max_depth),
+            ))
+        }
+        ControlParam::LfoRate => {
+            let lfo_index = settings
+                .lfo_index_from_midi_event(event.control)
+                .ok_or(Error::MissingLfo)?;
+            Ok(ControlMessage::SetLfoRate(
+                lfo_index,
+                midi_to_lfo_rate(event.value),
+            ))
+        }
+        ControlParam::LfoTarget => {
+            let lfo_index = settings
+                .lfo_index_from_midi_event(event.control)
+                .ok_or(Error::MissingLfo)?;
+            Ok(ControlMessage::SetLfoTarget(
+                lfo_index,
+                audio_channel,
+                midi_to_lfo_target(event.value),
+            ))
+        }
+        ControlParam::LoopLength => Ok(ControlMessage::SetChannelLoopLength(
+            audio_channel,
+            midi_to_range(event.value, 0.0, 1.0),
+        )),
+        ControlParam::LoopStart => Ok(ControlMessage::SetChannelLoopStart(
+            audio_channel,
+            midi_to_range(event.value, 0.0, 1.0),
+        )),
+        ControlParam::Pan => Ok(ControlMessage::SetChannelPan(
+            audio_channel,
+            midi_to_range(event.value, -1.0, 1.0),
+        )),
+        ControlParam::Pitch => Ok(ControlMessage::SetChannelPitch(
+            audio_channel,
+            midi_to_range(event.value, -MIDI_PITCH_RANGE, MIDI_PITCH_RANGE).round(),
+        )),
+        ControlParam::PlaybackMode => Ok(ControlMessage::SetChannelPlaybackMode(
+            audio_channel,
+            midi_to_playback_mode(event.value),
+        )),
+        ControlParam::Rate => {
+            let (min, max) = settings
+                .rate_range(audio_channel)
+                .ok_or(Error::MissingAudioChannel)?;
+            Ok(ControlMessage::SetChannelRate(
+                audio_channel,
+                midi_to_range(event.value, min, max),
+            ))
+        }
+        ControlParam::Record => match midi_to_switch(event.value) {
+            true => Ok(ControlMessage::StartChannelRecording(audio_channel)),
+            false => Ok(ControlMessage::StopChannelRecording(audio_channel)),
+        },
+        ControlParam::ReverbReturn => Ok(ControlMessage::SetReverbReturn(midi_to_percent(
+            event.value,
+        ))),
+        ControlParam::ReverbSend => Ok(ControlMessage::SetChannelReverbSend(
             audio_channel,
             midi_to_percent(event.value),
         )),
@@ -166,6 +302,35 @@ fn midi_to_percent(midi_value: u8) -> f32 {
     value
 }
 
+fn midi_to_filter_type(midi_value: u8) -> FilterType {
+    let index = midi_value as usize * FilterType::ALL.len() / 128;
+    FilterType::ALL[index]
+}
+
+fn midi_to_playback_mode(midi_value: u8) -> PlaybackMode {
+    let index = midi_value as usize * PlaybackMode::ALL.len() / 128;
+    PlaybackMode::ALL[index]
+}
+
+fn midi_to_lfo_rate(midi_value: u8) -> f32 {
+    // squared so more of the range is given to slow rates
+    let value = midi_value as f32 / 127.0;
+    value * value * MAX_LFO_RATE
+}
+
+fn midi_to_lfo_target(midi_value: u8) -> LfoTarget {
+    let index = midi_value as usize * LfoTarget::ALL.len() / 128;
+    LfoTarget::ALL[index]
+}
+
+fn midi_to_switch(midi_value: u8) -> bool {
+    midi_value >= 64
+}
+
+fn midi_to_range(midi_value: u8, min: f32, max: f32) -> f32 {
+    min + (max - min) / 127.0 * midi_value as f32
+}
+
 fn midi_to_freq(midi_value: u8) -> f32 {
     let value = midi_value as f32;
 
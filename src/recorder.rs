@@ -0,0 +1,202 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use web_audio_api::context::{AudioContext, BaseAudioContext};
+use web_audio_api::node::{AudioNode, GainNode, MediaStreamAudioDestinationNode};
+use web_audio_api::AudioBuffer;
+
+use crate::recording::{self, WAV_BYTES_PER_SAMPLE};
+use crate::settings::RecorderSettings;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to create recording directory")]
+    CreateDir(#[from] io::Error),
+    #[error("recording thread panicked")]
+    RecordingThread,
+    #[error("failed to write recording to wav file")]
+    WriteWav(#[from] hound::Error),
+}
+
+type Writer = hound::WavWriter<BufWriter<File>>;
+
+/// Streams the master output to wav files on disk, starting a new file once the current one
+/// reaches the configured size
+pub struct Recorder {
+    output: MediaStreamAudioDestinationNode,
+    stop: Arc<AtomicBool>,
+    capture: JoinHandle<()>,
+    writer: JoinHandle<()>,
+    writing: Arc<AtomicBool>,
+    /// Files are named by when the recording started and numbered from 1
+    timestamp: u64,
+    /// Number of the file being written, 0 before the first
+    part: Arc<AtomicUsize>,
+}
+
+impl Recorder {
+    /// Start recording, `writing` is set while the recording is being written and cleared once it
+    /// stops, including when writing fails
+    pub fn start(
+        context: &AudioContext,
+        master: &GainNode,
+        settings: &RecorderSettings,
+        writing: Arc<AtomicBool>,
+    ) -> Result<Self, Error> {
+        Self::record(
+            context,
+            master,
+            settings,
+            writing,
+            recording::timestamp(),
+            0,
+        )
+    }
+
+    /// Carry on recording the master output of a rebuilt audio graph once the old context has
+    /// been closed, in the next file of the same recording
+    pub fn restart(
+        self,
+        context: &AudioContext,
+        master: &GainNode,
+        settings: &RecorderSettings,
+    ) -> Result<Self, Error> {
+        let writing = self.writing.clone();
+        let timestamp = self.timestamp;
+        let part = self.part.load(Ordering::SeqCst);
+        self.finish()?;
+
+        Self::record(context, master, settings, writing, timestamp, part)
+    }
+
+    fn record(
+        context: &AudioContext,
+        master: &GainNode,
+        settings: &RecorderSettings,
+        writing: Arc<AtomicBool>,
+        timestamp: u64,
+        part: usize,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(settings.dir())?;
+
+        let output = context.create_media_stream_destination();
+        master.connect(&output);
+
+        let spec = recording::wav_spec(output.channel_count(), context.sample_rate());
+        let max_file_samples = settings.max_file_size() / WAV_BYTES_PER_SAMPLE;
+        let dir = PathBuf::from(settings.dir());
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_requested = stop.clone();
+        let stream = output.stream();
+        let (buffer_tx, buffer_rx) = channel::<AudioBuffer>();
+        let part = Arc::new(AtomicUsize::new(part));
+        let file_part = part.clone();
+
+        // buffers are queued here and written to disk on another thread to keep up with the
+        // render thread
+        let capture = thread::spawn(move || {
+            recording::capture(stream, &stop_requested, |buffer| {
+                buffer_tx.send(buffer).is_ok()
+            });
+        });
+
+        writing.store(true, Ordering::SeqCst);
+        let writer_writing = writing.clone();
+        let writer = thread::spawn(move || {
+            let file = RecordingFile {
+                dir,
+                spec,
+                max_samples: max_file_samples,
+                timestamp,
+                part: file_part,
+            };
+            if let Err(err) = write_recording(buffer_rx, &file) {
+                println!(
+                    "master recording stopped, failed to write it to disk: {}",
+                    err
+                );
+            }
+            writer_writing.store(false, Ordering::SeqCst);
+        });
+
+        Ok(Self {
+            output,
+            stop,
+            capture,
+            writer,
+            writing,
+            timestamp,
+            part,
+        })
+    }
+
+    /// Stop recording and wait for the queued audio to be written and the current file to be
+    /// finalised
+    pub fn stop(self, master: &GainNode) -> Result<(), Error> {
+        master.disconnect_from(&self.output);
+        self.finish()
+    }
+
+    /// Stop capturing and wait for the writer to finish with what was captured
+    fn finish(self) -> Result<(), Error> {
+        let capture = recording::stop_capture(&self.stop, self.capture);
+        let writer = self.writer.join();
+
+        capture.and(writer).map_err(|_| Error::RecordingThread)
+    }
+}
+
+/// Where and how a recording is written
+struct RecordingFile {
+    dir: PathBuf,
+    spec: hound::WavSpec,
+    /// Samples written to a file before moving on to the next
+    max_samples: u64,
+    timestamp: u64,
+    part: Arc<AtomicUsize>,
+}
+
+/// Write buffers to wav files until the capture thread stops sending them
+fn write_recording(buffers: Receiver<AudioBuffer>, file: &RecordingFile) -> Result<(), Error> {
+    let spec = file.spec;
+    let mut writer: Option<Writer> = None;
+
+    for buffer in buffers {
+        let file_full = writer
+            .as_ref()
+            .map(|writer| writer.len() as u64 >= file.max_samples)
+            .unwrap_or(true);
+        if file_full {
+            if let Some(full) = writer.take() {
+                full.finalize()?;
+            }
+            let part = file.part.fetch_add(1, Ordering::SeqCst) + 1;
+            let path = recording_path(&file.dir, file.timestamp, part);
+            println!("recording master output to {:?}", path);
+            writer = Some(hound::WavWriter::create(path, spec)?);
+        }
+
+        let writer = writer.as_mut().expect("Wav writer is open");
+        for frame in 0..buffer.length() {
+            for channel_number in 0..spec.channels as usize {
+                let channel_number = channel_number.min(buffer.number_of_channels() - 1);
+                let sample = buffer.get_channel_data(channel_number)[frame];
+                writer.write_sample(recording::wav_sample(sample))?;
+            }
+        }
+    }
+
+    match writer {
+        Some(writer) => writer.finalize().map_err(Error::WriteWav),
+        None => Ok(()),
+    }
+}
+
+fn recording_path(dir: &Path, timestamp: u64, part: usize) -> PathBuf {
+    dir.join(format!("performance-{}-{:03}.wav", timestamp, part))
+}
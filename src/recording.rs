@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use web_audio_api::media::MediaStream;
+use web_audio_api::AudioBuffer;
+
+/// Recordings are written as 24 bit integer wav files
+const WAV_BITS_PER_SAMPLE: u16 = 24;
+pub const WAV_BYTES_PER_SAMPLE: u64 = WAV_BITS_PER_SAMPLE as u64 / 8;
+/// Largest value of a wav sample, full scale audio is scaled to this
+const WAV_MAX_AMPLITUDE: f32 = (1 << (WAV_BITS_PER_SAMPLE - 1)) as f32 - 1.0;
+
+pub fn wav_spec(channels: usize, sample_rate: f32) -> hound::WavSpec {
+    hound::WavSpec {
+        channels: channels as u16,
+        sample_rate: sample_rate as u32,
+        bits_per_sample: WAV_BITS_PER_SAMPLE,
+        sample_format: hound::SampleFormat::Int,
+    }
+}
+
+/// A sample of audio as a wav sample, clipped to full scale
+pub fn wav_sample(sample: f32) -> i32 {
+    (sample.clamp(-1.0, 1.0) * WAV_MAX_AMPLITUDE) as i32
+}
+
+/// Seconds since the epoch, recordings are named by when they started
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Hand each buffer rendered into `stream` to `take` until `stop` is set, `take` returns false or
+/// the audio context goes. The render thread drops any buffer that hasn't been taken by the time
+/// it renders the next one, so `take` must keep up with it.
+pub fn capture(
+    stream: impl MediaStream,
+    stop: &AtomicBool,
+    mut take: impl FnMut(AudioBuffer) -> bool,
+) {
+    for buffer in stream {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        // the stream only fails once the audio context has gone
+        let Ok(buffer) = buffer else {
+            break;
+        };
+        if !take(buffer) {
+            break;
+        }
+    }
+}
+
+/// Stop a capture thread and wait for it to finish. Only the thread is stopped, the audio graph's
+/// nodes are left alone as its context may already be closed.
+pub fn stop_capture<T>(stop: &AtomicBool, capture: JoinHandle<T>) -> thread::Result<T> {
+    stop.store(true, Ordering::SeqCst);
+    capture.join()
+}
@@ -1,34 +1,261 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
-use std::{io, path::PathBuf};
+use std::io;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
 
-use crate::settings::Settings;
+use crate::grid::GridMessage;
+use crate::metadata::{self, SampleMetadata};
+use crate::sample_watcher::spawn_sample_watcher;
+use crate::settings::{SampleOrder, Settings};
 
 const HIDDEN_FILE: &str = "._";
-const WAV_EXTENSION: &str = "wav";
+/// Lists a sample dir's file names in the order they are mapped to the grid
+const ORDER_FILE: &str = "order.txt";
+
+/// A directory of samples within a sample dir, named by its path relative to the sample dir
+#[derive(Debug)]
+struct Bank {
+    name: String,
+    path: PathBuf,
+    /// Sample files in the bank, in the order they are mapped to the grid
+    entries: Vec<PathBuf>,
+}
 
 #[derive(Debug)]
 pub struct SampleDir {
     path: PathBuf,
+    /// File extensions treated as samples, without the leading dot
+    extensions: Vec<String>,
+    order: SampleOrder,
+    /// The sample dir and every directory below it that holds samples
+    banks: Vec<Bank>,
+    active_bank: usize,
 }
 
 impl SampleDir {
-    fn from_path(path: &str) -> Self {
-        Self { path: path.into() }
+    fn from_path(path: &str, extensions: &[String], order: SampleOrder) -> Self {
+        let mut dir = Self {
+            path: path.into(),
+            extensions: extensions.to_vec(),
+            order,
+            banks: Vec::new(),
+            active_bank: 0,
+        };
+        dir.banks = dir.find_banks();
+        dir
+    }
+
+    /// Directories holding samples in natural order of their names, with the sample dir itself
+    /// first. The sample dir is always a bank when no directory holds samples.
+    fn find_banks(&self) -> Vec<Bank> {
+        let mut banks = Vec::new();
+
+        for path in dirs_below(&self.path) {
+            let entries = self.bank_entries(&path).unwrap_or_default();
+            if !entries.is_empty() {
+                let name = path
+                    .strip_prefix(&self.path)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned();
+                banks.push(Bank {
+                    name,
+                    path,
+                    entries,
+                });
+            }
+        }
+
+        if banks.is_empty() {
+            banks.push(Bank {
+                name: String::new(),
+                path: self.path.clone(),
+                entries: Vec::new(),
+            });
+        }
+        // the sample dir's name is empty, so it sorts first
+        banks.sort_by(|a, b| natural_cmp(&a.name, &b.name).then_with(|| a.name.cmp(&b.name)));
+        banks
+    }
+
+    /// Find the banks and their samples again, keeping the active bank if it still exists
+    fn refresh_banks(&mut self) {
+        let active_path = self.active_bank().path.clone();
+        self.banks = self.find_banks();
+        self.active_bank = self
+            .banks
+            .iter()
+            .position(|bank| bank.path == active_path)
+            .unwrap_or(0);
+    }
+
+    fn active_bank(&self) -> &Bank {
+        &self.banks[self.active_bank]
     }
 
-    fn entries(&self) -> Result<Vec<PathBuf>, io::Error> {
-        let entries = fs::read_dir(&self.path)?
+    /// Make the bank at `bank_index` active, returns `None` if it doesn't exist
+    fn select_bank(&mut self, bank_index: usize) -> Option<&Bank> {
+        self.banks.get(bank_index)?;
+        self.active_bank = bank_index;
+        Some(self.active_bank())
+    }
+
+    /// Move through the banks by `step`, wrapping around at either end
+    fn step_bank(&mut self, step: isize) -> &Bank {
+        let count = self.banks.len() as isize;
+        self.active_bank = (self.active_bank as isize + step).rem_euclid(count) as usize;
+        self.active_bank()
+    }
+
+    /// Sample files in a bank sorted by the dir's sample order, names are always sorted naturally
+    /// so that "9" comes before "10" and files that tie on the sample order stay in a stable order
+    fn bank_entries(&self, bank_path: &Path) -> Result<Vec<PathBuf>, io::Error> {
+        let mut entries = fs::read_dir(bank_path)?
             .map(|dir| dir.map(|entry| entry.path()))
-            .filter(is_valid_file)
+            .filter(|path| match path {
+                Ok(path) => self.is_sample_file(path),
+                Err(_) => false,
+            })
             .collect::<Result<Vec<PathBuf>, io::Error>>()?;
 
+        entries.sort_by(|a, b| {
+            natural_cmp(file_name(a), file_name(b)).then_with(|| file_name(a).cmp(file_name(b)))
+        });
+        match self.order {
+            SampleOrder::Name => {}
+            // oldest first, so new recordings are added after the existing samples
+            SampleOrder::Mtime => entries.sort_by_cached_key(|path| {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            }),
+            SampleOrder::Manifest => apply_manifest(bank_path, &mut entries),
+        }
+
         Ok(entries)
     }
+
+    /// Files with an allowed extension, in any case, that aren't macOS resource forks
+    fn is_sample_file(&self, path: &Path) -> bool {
+        let Some(file_name) = path.file_name().and_then(OsStr::to_str) else {
+            return false;
+        };
+        let Some(extension) = path.extension().and_then(OsStr::to_str) else {
+            return false;
+        };
+
+        path.is_file()
+            && !file_name.starts_with(HIDDEN_FILE)
+            && self
+                .extensions
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(extension))
+    }
+}
+
+/// Every channel's sample dir, the samples found in it and their metadata, shared with the
+/// watcher that keeps it up to date
+#[derive(Clone, Debug)]
+pub struct SampleIndex {
+    dirs: Arc<RwLock<Vec<SampleDir>>>,
+    metadata: Arc<RwLock<HashMap<PathBuf, SampleMetadata>>>,
+    /// Held while measuring samples so only one thread decodes and writes metadata files
+    metadata_scan: Arc<Mutex<()>>,
+}
+
+impl SampleIndex {
+    /// Sample dir paths and every directory below them, the directories to watch for changes
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        self.read()
+            .iter()
+            .flat_map(|dir| dirs_below(&dir.path))
+            .collect()
+    }
+
+    /// Find every sample dir's banks and samples again after files have been added or removed
+    pub fn refresh(&self) {
+        for dir in self.write().iter_mut() {
+            dir.refresh_banks();
+        }
+    }
+
+    /// Read the metadata of every sample, measuring those that are new or have changed since the
+    /// metadata files were written. Samples are decoded to measure them, so this can be slow.
+    pub fn update_metadata(&self) {
+        let _scan = self.metadata_scan.lock().expect("Metadata scan lock");
+        // channels can share a sample dir, each bank is only read once
+        let banks: BTreeMap<PathBuf, Vec<PathBuf>> = self
+            .read()
+            .iter()
+            .flat_map(|dir| dir.banks.iter())
+            .map(|bank| (bank.path.clone(), bank.entries.clone()))
+            .collect();
+
+        let mut records = HashMap::new();
+        for (path, entries) in banks {
+            let bank_records = metadata::read_dir_metadata(&path, &entries);
+            // each bank is usable as soon as it is done
+            self.metadata
+                .write()
+                .expect("Sample metadata lock")
+                .extend(bank_records.clone());
+            records.extend(bank_records);
+        }
+        // drops the records of samples that have been removed
+        *self.metadata.write().expect("Sample metadata lock") = records;
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Vec<SampleDir>> {
+        self.dirs.read().expect("Sample index lock")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<SampleDir>> {
+        self.dirs.write().expect("Sample index lock")
+    }
+}
+
+/// Number of samples in each bank of each channel's sample dir and which bank is active, a read
+/// only view of the sample index that stays up to date as it is refreshed
+#[derive(Clone, Debug)]
+pub struct SampleCounts(SampleIndex);
+
+impl SampleCounts {
+    pub fn bank_count(&self, channel_index: usize) -> usize {
+        self.0
+            .read()
+            .get(channel_index)
+            .map(|dir| dir.banks.len())
+            .unwrap_or_default()
+    }
+
+    pub fn active_bank(&self, channel_index: usize) -> usize {
+        self.0
+            .read()
+            .get(channel_index)
+            .map(|dir| dir.active_bank)
+            .unwrap_or_default()
+    }
+
+    pub fn sample_count(&self, channel_index: usize, bank_index: usize) -> usize {
+        self.0
+            .read()
+            .get(channel_index)
+            .and_then(|dir| dir.banks.get(bank_index))
+            .map(|bank| bank.entries.len())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug)]
 pub struct SampleManager {
-    dirs: Vec<SampleDir>,
+    index: SampleIndex,
 }
 
 impl SampleManager {
@@ -36,9 +263,110 @@ impl SampleManager {
         let dirs = settings
             .sample_dirs()
             .iter()
-            .map(|path| SampleDir::from_path(path))
+            .zip(settings.channels())
+            .map(|(path, channel)| {
+                SampleDir::from_path(path, settings.sample_extensions(), channel.sample_order())
+            })
             .collect();
-        Self { dirs }
+
+        Self {
+            index: SampleIndex {
+                dirs: Arc::new(RwLock::new(dirs)),
+                metadata: Arc::default(),
+                metadata_scan: Arc::default(),
+            },
+        }
+    }
+
+    /// A view of the number of samples in each bank that follows later refreshes
+    pub fn sample_counts(&self) -> SampleCounts {
+        SampleCounts(self.index.clone())
+    }
+
+    /// Keep the sample index up to date as files are added to or removed from the sample dirs,
+    /// asking the grid to redraw after each change
+    pub fn start_watcher(&self, grid_tx: Sender<GridMessage>) {
+        if let Err(err) = spawn_sample_watcher(self.index.clone(), grid_tx) {
+            println!(
+                "failed to watch sample dirs, new samples won't be found: {}",
+                err
+            );
+        }
+    }
+
+    /// Read the metadata of every sample in the background, each bank's metadata is available as
+    /// soon as it has been read
+    pub fn start_metadata_scan(&self) {
+        let index = self.index.clone();
+        thread::spawn(move || index.update_metadata());
+    }
+
+    /// Find banks and samples again, for changes that can't wait for the watcher
+    pub fn refresh(&self) {
+        self.index.refresh();
+    }
+
+    /// The channel's active bank, where recordings are saved
+    pub fn get_dir_for_channel(&self, channel_index: usize) -> Option<PathBuf> {
+        let dirs = self.index.read();
+        let dir = dirs.get(channel_index)?;
+        Some(dir.active_bank().path.clone())
+    }
+
+    /// Make a bank active, returning its name
+    pub fn select_bank(&self, channel_index: usize, bank_index: usize) -> Option<String> {
+        let mut dirs = self.index.write();
+        let bank = dirs.get_mut(channel_index)?.select_bank(bank_index)?;
+        Some(bank.name.clone())
+    }
+
+    /// Make the next bank active, returning its name
+    pub fn next_bank(&self, channel_index: usize) -> Option<String> {
+        let mut dirs = self.index.write();
+        Some(dirs.get_mut(channel_index)?.step_bank(1).name.clone())
+    }
+
+    /// Make the previous bank active, returning its name
+    pub fn previous_bank(&self, channel_index: usize) -> Option<String> {
+        let mut dirs = self.index.write();
+        Some(dirs.get_mut(channel_index)?.step_bank(-1).name.clone())
+    }
+
+    /// Samples in the channel's active bank
+    pub fn bank_paths(&self, channel_index: usize) -> Vec<PathBuf> {
+        let dirs = self.index.read();
+        dirs.get(channel_index)
+            .map(|dir| dir.active_bank().entries.clone())
+            .unwrap_or_default()
+    }
+
+    /// Every channel's samples in its active bank, taking one from each channel in turn so that
+    /// all channels' first samples come first
+    pub fn preload_paths(&self) -> Vec<PathBuf> {
+        let dirs = self.index.read();
+        let entries: Vec<&Vec<PathBuf>> =
+            dirs.iter().map(|dir| &dir.active_bank().entries).collect();
+        let longest = entries
+            .iter()
+            .map(|paths| paths.len())
+            .max()
+            .unwrap_or_default();
+
+        (0..longest)
+            .flat_map(|index| entries.iter().filter_map(move |paths| paths.get(index)))
+            .cloned()
+            .collect()
+    }
+
+    /// Metadata of a sample in the channel's active bank, `None` until it has been read
+    pub fn get_metadata_for_sample(
+        &self,
+        channel_index: usize,
+        sample_index: usize,
+    ) -> Option<SampleMetadata> {
+        let path = self.get_path_for_sample(channel_index, sample_index)?;
+        let metadata = self.index.metadata.read().expect("Sample metadata lock");
+        metadata.get(&path).cloned()
     }
 
     pub fn get_path_for_sample(
@@ -46,18 +374,198 @@ impl SampleManager {
         channel_index: usize,
         sample_index: usize,
     ) -> Option<PathBuf> {
-        let entries = self.dirs.get(channel_index)?.entries().ok()?;
-        let path = entries.get(sample_index)?;
+        let dirs = self.index.read();
+        let path = dirs
+            .get(channel_index)?
+            .active_bank()
+            .entries
+            .get(sample_index)?;
         Some(path.clone())
     }
 }
 
-fn is_valid_file(path: &Result<PathBuf, io::Error>) -> bool {
-    match path {
-        Ok(path) => {
-            let path = path.as_os_str().to_string_lossy();
-            path.contains(WAV_EXTENSION) && !path.contains(HIDDEN_FILE)
+/// Move the files listed in a bank's order file, one name per line, to the front in the order
+/// they are listed. Files that aren't listed follow in name order.
+fn apply_manifest(bank_path: &Path, entries: &mut [PathBuf]) {
+    let manifest = fs::read_to_string(bank_path.join(ORDER_FILE)).unwrap_or_default();
+    let mut positions = HashMap::new();
+    let names = manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    for (position, name) in names.enumerate() {
+        positions.entry(name).or_insert(position);
+    }
+
+    entries.sort_by_key(|path| {
+        positions
+            .get(file_name(path))
+            .copied()
+            .unwrap_or(usize::MAX)
+    });
+}
+
+/// `root` and every directory below it. Symlinked directories are followed, but each directory is
+/// only visited once so links back up the tree don't loop forever.
+fn dirs_below(root: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(path) = pending.pop() {
+        let Ok(canonical) = fs::canonicalize(&path) else {
+            continue;
+        };
+        if !visited.insert(canonical) {
+            continue;
+        }
+        pending.extend(sub_dirs(&path));
+        dirs.push(path);
+    }
+    dirs
+}
+
+/// Directories directly inside `path`, skipping hidden ones
+fn sub_dirs(path: &Path) -> Vec<PathBuf> {
+    let Ok(dir) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+    dir.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && !file_name(path).starts_with('.'))
+        .collect()
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(OsStr::to_str).unwrap_or_default()
+}
+
+/// Compare names ignoring case, with runs of digits compared by their value
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                compare_numbers(&take_digits(&mut a), &take_digits(&mut b))
+            }
+            (Some(&x), Some(&y)) => {
+                a.next();
+                b.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
         }
-        Err(_) => false,
+    }
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+    digits
+}
+
+/// Compare digit strings of any length by value, with more leading zeros after fewer
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a_value = a.trim_start_matches('0');
+    let b_value = b.trim_start_matches('0');
+
+    a_value
+        .len()
+        .cmp(&b_value.len())
+        .then_with(|| a_value.cmp(b_value))
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(natural_cmp("kick9", "kick10"), Ordering::Less);
+        assert_eq!(natural_cmp("kick10", "kick9"), Ordering::Greater);
+        assert_eq!(
+            sorted(&["loop 10.wav", "loop 2.wav", "loop 1.wav", "loop 100.wav"]),
+            ["loop 1.wav", "loop 2.wav", "loop 10.wav", "loop 100.wav"]
+        );
+    }
+
+    #[test]
+    fn leading_zeros_sort_after_fewer() {
+        assert_eq!(compare_numbers("1", "01"), Ordering::Less);
+        assert_eq!(compare_numbers("007", "7"), Ordering::Greater);
+        assert_eq!(compare_numbers("09", "10"), Ordering::Less);
+        assert_eq!(compare_numbers("0", "00"), Ordering::Less);
+        assert_eq!(compare_numbers("42", "42"), Ordering::Equal);
+        assert_eq!(
+            sorted(&["take 02", "take 1", "take 01", "take 10"]),
+            ["take 1", "take 01", "take 02", "take 10"]
+        );
+    }
+
+    #[test]
+    fn numbers_longer_than_an_integer() {
+        assert_eq!(
+            compare_numbers("99999999999999999999999", "100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn letters_ignore_case() {
+        assert_eq!(natural_cmp("Snare", "snare"), Ordering::Equal);
+        assert_eq!(natural_cmp("bass", "Drums"), Ordering::Less);
+        assert_eq!(
+            sorted(&["Pad.wav", "arp.wav", "Bass.wav", "pad2.wav"]),
+            ["arp.wav", "Bass.wav", "Pad.wav", "pad2.wav"]
+        );
+    }
+
+    #[test]
+    fn manifest_order_with_unlisted_files_appended() {
+        let bank = std::env::temp_dir().join(format!("biome-manifest-test-{}", std::process::id()));
+        fs::create_dir_all(&bank).expect("Test bank created");
+        fs::write(
+            bank.join(ORDER_FILE),
+            "# set order\nsnare.wav\n\n  kick.wav  \nmissing.wav\nsnare.wav\n",
+        )
+        .expect("Test manifest written");
+        let mut entries: Vec<PathBuf> = ["a.wav", "kick.wav", "b.wav", "snare.wav"]
+            .iter()
+            .map(|name| bank.join(name))
+            .collect();
+
+        apply_manifest(&bank, &mut entries);
+        fs::remove_dir_all(&bank).expect("Test bank removed");
+
+        let names: Vec<&str> = entries.iter().map(|path| file_name(path)).collect();
+        assert_eq!(names, ["snare.wav", "kick.wav", "a.wav", "b.wav"]);
+    }
+
+    #[test]
+    fn missing_manifest_keeps_order() {
+        let bank =
+            std::env::temp_dir().join(format!("biome-no-manifest-test-{}", std::process::id()));
+        let mut entries = vec![bank.join("b.wav"), bank.join("a.wav")];
+
+        apply_manifest(&bank, &mut entries);
+
+        assert_eq!(entries, [bank.join("b.wav"), bank.join("a.wav")]);
     }
 }
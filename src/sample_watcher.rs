@@ -0,0 +1,82 @@
+use std::io;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use std::ffi::OsStr;
+
+use inotify::{Event, EventMask, Inotify, WatchMask};
+
+use crate::grid::GridMessage;
+use crate::metadata::METADATA_FILE;
+use crate::sample_manager::SampleIndex;
+
+/// Room for a few dozen events with long file names per read
+const EVENT_BUFFER_SIZE: usize = 16 * 1024;
+/// Files copied in together arrive as a burst of events, the index is refreshed once it settles
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Watch the sample dirs and the directories below them with inotify, refreshing the index and
+/// asking the grid to redraw whenever a sample or bank appears or disappears
+pub fn spawn_sample_watcher(index: SampleIndex, grid_tx: Sender<GridMessage>) -> io::Result<()> {
+    let mut inotify = Inotify::init()?;
+    watch_dirs(&inotify, &index);
+
+    thread::spawn(move || {
+        let mut buffer = [0; EVENT_BUFFER_SIZE];
+
+        loop {
+            let changed = match inotify.read_events_blocking(&mut buffer) {
+                Ok(mut events) => events.any(|event| is_sample_change(&event)),
+                Err(err) => {
+                    println!("stopped watching sample dirs: {}", err);
+                    break;
+                }
+            };
+            if !changed {
+                continue;
+            }
+
+            thread::sleep(SETTLE_TIME);
+            // the refresh picks up everything that happened while settling
+            while let Ok(events) = inotify.read_events(&mut buffer) {
+                if events.count() == 0 {
+                    break;
+                }
+            }
+
+            index.refresh();
+            // new directories need watching too, dirs that are already watched are unchanged
+            watch_dirs(&inotify, &index);
+            if grid_tx.send(GridMessage::Redraw).is_err() {
+                break;
+            }
+            index.update_metadata();
+        }
+    });
+
+    Ok(())
+}
+
+fn watch_dirs(inotify: &Inotify, index: &SampleIndex) {
+    let mask = WatchMask::CREATE
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO;
+
+    for dir in index.dirs() {
+        if let Err(err) = inotify.watches().add(&dir, mask) {
+            println!("failed to watch {:?}: {}", dir, err);
+        }
+    }
+}
+
+/// A new file is ignored until it has been written and closed, so a sample that is still being
+/// copied in doesn't trigger a refresh. Writing metadata files doesn't either.
+fn is_sample_change(event: &Event<&OsStr>) -> bool {
+    let created_file =
+        event.mask.contains(EventMask::CREATE) && !event.mask.contains(EventMask::ISDIR);
+    let metadata_file = event.name == Some(OsStr::new(METADATA_FILE));
+    !created_file && !metadata_file
+}
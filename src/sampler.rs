@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use web_audio_api::context::{AudioContext, AudioContextOptions, BaseAudioContext};
+use web_audio_api::media::Microphone;
+use web_audio_api::node::{AudioNode, MediaStreamAudioDestinationNode, MediaStreamAudioSourceNode};
+use web_audio_api::AudioBuffer;
+
+use crate::recording;
+
+/// Longest recording kept in memory, any input after this is discarded
+const MAX_RECORDING_SECONDS: f32 = 60.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no audio was recorded")]
+    EmptyRecording,
+    #[error("recording thread panicked")]
+    RecordingThread,
+    #[error("failed to write recording to wav file")]
+    WriteWav(#[from] hound::Error),
+}
+
+/// Records the audio input into memory so it can be looped on a channel
+pub struct Sampler {
+    channel_index: usize,
+    sample_rate: f32,
+    microphone: Microphone,
+    input: MediaStreamAudioSourceNode,
+    _output: MediaStreamAudioDestinationNode,
+    stop: Arc<AtomicBool>,
+    recording: JoinHandle<Vec<Vec<f32>>>,
+}
+
+impl Sampler {
+    pub fn start(context: &AudioContext, channel_index: usize) -> Self {
+        Self::record(context, channel_index, vec![])
+    }
+
+    /// Carry on recording on the context of a rebuilt audio graph once the old context has been
+    /// closed, keeping what has been recorded so far
+    pub fn restart(self, context: &AudioContext) -> Self {
+        let channel_index = self.channel_index;
+        let sample_rate = self.sample_rate;
+        let mut recorded = self.finish().unwrap_or_default();
+
+        if context.sample_rate() != sample_rate {
+            println!("discarding audio input recorded at a different sample rate");
+            recorded.clear();
+        }
+        Self::record(context, channel_index, recorded)
+    }
+
+    /// Record the audio input after the audio already `recorded`
+    fn record(context: &AudioContext, channel_index: usize, recorded: Vec<Vec<f32>>) -> Self {
+        let sample_rate = context.sample_rate();
+        let microphone = Microphone::new(AudioContextOptions {
+            sample_rate: Some(sample_rate),
+            ..AudioContextOptions::default()
+        });
+        let input = context.create_media_stream_source(microphone.stream());
+        let output = context.create_media_stream_destination();
+        input.connect(&output);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_requested = stop.clone();
+        let stream = output.stream();
+        let max_length = (MAX_RECORDING_SECONDS * sample_rate) as usize;
+
+        let recording = thread::spawn(move || {
+            let mut samples = recorded;
+
+            recording::capture(stream, &stop_requested, |buffer| {
+                if samples.len() < buffer.number_of_channels() {
+                    samples.resize(buffer.number_of_channels(), vec![]);
+                }
+                if samples[0].len() < max_length {
+                    for (channel_number, channel) in samples.iter_mut().enumerate() {
+                        channel.extend_from_slice(buffer.get_channel_data(channel_number));
+                    }
+                }
+                true
+            });
+
+            samples
+        });
+
+        Self {
+            channel_index,
+            sample_rate,
+            microphone,
+            input,
+            _output: output,
+            stop,
+            recording,
+        }
+    }
+
+    pub fn channel_index(&self) -> usize {
+        self.channel_index
+    }
+
+    /// Stop recording, save the audio to a timestamped wav file in `dir` and return its path along
+    /// with the audio as a buffer
+    pub fn stop(self, dir: &Path) -> Result<(PathBuf, AudioBuffer), Error> {
+        let sample_rate = self.sample_rate;
+        self.input.disconnect();
+        let samples = self.finish()?;
+
+        if samples.is_empty() || samples[0].is_empty() {
+            return Err(Error::EmptyRecording);
+        }
+
+        let path = recording_path(dir);
+        println!("writing recording to {:?}", path);
+        write_wav(&path, &samples, sample_rate)?;
+
+        Ok((path, AudioBuffer::from(samples, sample_rate)))
+    }
+
+    /// Stop capturing and release the audio input, returning what was recorded
+    fn finish(self) -> Result<Vec<Vec<f32>>, Error> {
+        let samples =
+            recording::stop_capture(&self.stop, self.recording).map_err(|_| Error::RecordingThread);
+        self.microphone.close();
+        samples
+    }
+}
+
+fn recording_path(dir: &Path) -> PathBuf {
+    dir.join(format!("recording-{}.wav", recording::timestamp()))
+}
+
+fn write_wav(path: &Path, samples: &[Vec<f32>], sample_rate: f32) -> Result<(), hound::Error> {
+    let spec = recording::wav_spec(samples.len(), sample_rate);
+    let mut writer = hound::WavWriter::create(path, spec)?;
+
+    for frame in 0..samples[0].len() {
+        for channel in samples {
+            writer.write_sample(recording::wav_sample(channel[frame]))?;
+        }
+    }
+
+    writer.finalize()
+}
@@ -5,26 +5,131 @@ use std::ops::Not;
 
 use config::Config;
 
-use crate::MAX_CHANNEL_COUNT;
+use crate::{MAX_BPM, MAX_CHANNEL_COUNT, MAX_DELAY_TIME, MAX_PITCH_SHIFT, MIN_BPM};
+
+/// Wav files hold at most 4 GiB, with room left for the header
+const MAX_RECORDER_FILE_SIZE_MB: u64 = 4095;
+/// Sample rates supported by the audio context
+const MIN_SAMPLE_RATE: f32 = 8000.0;
+const MAX_SAMPLE_RATE: f32 = 96000.0;
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct Settings {
     midi_channel: u8,
     midi_device: String,
+    #[serde(default = "default_bpm")]
+    bpm: f32,
+    #[serde(default = "default_sample_extensions")]
+    sample_extensions: Vec<String>,
     channels: Vec<ChannelSettings>,
     midi: Vec<MidiSettings>,
+    #[serde(default)]
+    audio: AudioSettings,
+    #[serde(default)]
+    cache: CacheSettings,
+    #[serde(default)]
+    recorder: RecorderSettings,
+    #[serde(default)]
+    effects: EffectsSettings,
+    #[serde(default)]
+    lfos: Vec<LfoSettings>,
+    #[serde(default)]
+    limiter: LimiterSettings,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct ChannelSettings {
     sample_dir: String,
+    #[serde(default = "default_rate_min")]
+    rate_min: f32,
+    #[serde(default = "default_rate_max")]
+    rate_max: f32,
+    #[serde(default = "default_crossfade_time")]
+    crossfade_time: f64,
+    #[serde(default)]
+    pan: f32,
+    #[serde(default)]
+    filter_type: FilterType,
+    #[serde(default)]
+    engine: ChannelEngine,
+    #[serde(default)]
+    playback_mode: PlaybackMode,
+    #[serde(default)]
+    trigger_note: Option<u8>,
+    #[serde(default)]
+    sync_beats: Option<f32>,
+    #[serde(default)]
+    pitch: f32,
+    #[serde(default)]
+    sample_order: SampleOrder,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct CacheSettings {
+    max_size_mb: u64,
+    preload: bool,
+    #[serde(default = "default_stream_above_mb")]
+    stream_above_mb: u64,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RecorderSettings {
+    dir: String,
+    max_file_size_mb: u64,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct EffectsSettings {
+    delay_time: f64,
+    delay_feedback: f32,
+    reverb_impulse: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct LimiterSettings {
+    threshold: f32,
+    ceiling: f32,
+    soft_clip: bool,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct LfoSettings {
+    shape: LfoShape,
+    rate: f32,
+    depth: f32,
+    channel: usize,
+    target: LfoTarget,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    SampleAndHold,
+    RandomWalk,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LfoTarget {
+    FilterFrequency,
+    FilterQ,
+    Pan,
+    Rate,
+    Volume,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct MidiSettings {
     param: ControlParam,
     cc_id: u8,
+    /// Ignored for params that are not specific to a channel
+    #[serde(default)]
     channel: u8,
+    /// Only used by lfo params
+    #[serde(default)]
+    lfo: usize,
     initial_value: u8,
 }
 
@@ -41,12 +146,333 @@ pub enum Error {
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ControlParam {
+    Bpm,
+    DelayReturn,
+    DelaySend,
     FilterFrequency,
     FilterQ,
+    FilterType,
+    GrainDensity,
+    GrainPitchJitter,
+    GrainPosition,
+    GrainSize,
+    GrainSpray,
+    LfoDepth,
+    LfoRate,
+    LfoTarget,
+    LoopLength,
+    LoopStart,
+    Pan,
+    Pitch,
+    PlaybackMode,
     Rate,
+    Record,
+    ReverbReturn,
+    ReverbSend,
     Volume,
 }
 
+impl ChannelSettings {
+    /// Seconds taken to crossfade between samples when a new one is selected
+    pub fn crossfade_time(&self) -> f64 {
+        self.crossfade_time
+    }
+
+    /// Initial stereo position, from -1.0 (left) to 1.0 (right)
+    pub fn pan(&self) -> f32 {
+        self.pan
+    }
+
+    pub fn filter_type(&self) -> FilterType {
+        self.filter_type
+    }
+
+    pub fn engine(&self) -> ChannelEngine {
+        self.engine
+    }
+
+    pub fn playback_mode(&self) -> PlaybackMode {
+        self.playback_mode
+    }
+
+    /// Beats the loop is stretched to fill at the global bpm, `None` to play it at its own length
+    pub fn sync_beats(&self) -> Option<f32> {
+        self.sync_beats
+    }
+
+    /// Initial pitch shift in semitones
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn sample_order(&self) -> SampleOrder {
+        self.sample_order
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct AudioSettings {
+    #[serde(default)]
+    sink_id: String,
+    #[serde(default)]
+    sample_rate: Option<f32>,
+    #[serde(default)]
+    latency_hint: LatencyHint,
+}
+
+/// Trade off between output latency and power use, either a category or a latency in seconds
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub enum LatencyHint {
+    Category(LatencyCategory),
+    Seconds(f64),
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyCategory {
+    Balanced,
+    #[default]
+    Interactive,
+    Playback,
+}
+
+/// How a channel plays back its sample
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelEngine {
+    /// Loop the whole sample
+    #[default]
+    Looper,
+    /// Spray short overlapping grains taken from the sample
+    Granular,
+}
+
+/// How the looper engine plays through the sample
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackMode {
+    #[default]
+    ForwardLoop,
+    ReverseLoop,
+    /// Loop forwards then backwards
+    PingPong,
+    /// Play the loop once each time the channel is triggered
+    OneShot,
+}
+
+/// How the files in a sample dir are ordered on the grid
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleOrder {
+    /// By file name, with numbers in names compared by value
+    #[default]
+    Name,
+    /// By modification time, oldest first
+    Mtime,
+    /// As listed in the dir's order.txt, unlisted files follow by name
+    Manifest,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterType {
+    Lowpass,
+    Highpass,
+    #[default]
+    Bandpass,
+    Notch,
+    Peaking,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::Lowpass,
+        FilterType::Highpass,
+        FilterType::Bandpass,
+        FilterType::Notch,
+        FilterType::Peaking,
+    ];
+}
+
+impl PlaybackMode {
+    pub const ALL: [PlaybackMode; 4] = [
+        PlaybackMode::ForwardLoop,
+        PlaybackMode::ReverseLoop,
+        PlaybackMode::PingPong,
+        PlaybackMode::OneShot,
+    ];
+}
+
+impl Default for LatencyHint {
+    fn default() -> Self {
+        Self::Category(LatencyCategory::default())
+    }
+}
+
+impl AudioSettings {
+    /// Output device to play through, empty for the system default
+    pub fn sink_id(&self) -> &str {
+        &self.sink_id
+    }
+
+    /// Sample rate to run at, `None` for the output device's rate
+    pub fn sample_rate(&self) -> Option<f32> {
+        self.sample_rate
+    }
+
+    pub fn latency_hint(&self) -> LatencyHint {
+        self.latency_hint
+    }
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 256,
+            preload: true,
+            stream_above_mb: default_stream_above_mb(),
+        }
+    }
+}
+
+impl CacheSettings {
+    /// Bytes of decoded samples kept in memory
+    pub fn max_size(&self) -> usize {
+        (self.max_size_mb * 1024 * 1024) as usize
+    }
+
+    /// Decode every channel's samples in the background at startup, and a bank's samples when it
+    /// is selected
+    pub fn preload(&self) -> bool {
+        self.preload
+    }
+
+    /// Files of at least this many bytes are streamed from disk instead of decoded into memory
+    pub fn stream_threshold(&self) -> u64 {
+        self.stream_above_mb * 1024 * 1024
+    }
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            dir: "./recordings".into(),
+            max_file_size_mb: 512,
+        }
+    }
+}
+
+impl RecorderSettings {
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    /// Size in bytes at which the recorder moves on to a new file
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size_mb * 1024 * 1024
+    }
+}
+
+impl Default for EffectsSettings {
+    fn default() -> Self {
+        Self {
+            delay_time: 0.5,
+            delay_feedback: 0.4,
+            reverb_impulse: None,
+        }
+    }
+}
+
+impl EffectsSettings {
+    pub fn delay_time(&self) -> f64 {
+        self.delay_time
+    }
+
+    pub fn delay_feedback(&self) -> f32 {
+        self.delay_feedback
+    }
+
+    /// Path to the wav file used as the reverb impulse response
+    pub fn reverb_impulse(&self) -> Option<&str> {
+        self.reverb_impulse.as_deref()
+    }
+}
+
+impl ControlParam {
+    fn is_grain_param(&self) -> bool {
+        matches!(
+            self,
+            ControlParam::GrainDensity
+                | ControlParam::GrainPitchJitter
+                | ControlParam::GrainPosition
+                | ControlParam::GrainSize
+                | ControlParam::GrainSpray
+        )
+    }
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            threshold: -3.0,
+            ceiling: -0.3,
+            soft_clip: true,
+        }
+    }
+}
+
+impl LimiterSettings {
+    /// Level in dB above which the master output is limited
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Level in dB the master output is clipped at
+    pub fn ceiling(&self) -> f32 {
+        self.ceiling
+    }
+
+    /// Round off the clipping rather than clipping hard at the ceiling
+    pub fn soft_clip(&self) -> bool {
+        self.soft_clip
+    }
+}
+
+impl LfoTarget {
+    pub const ALL: [LfoTarget; 5] = [
+        LfoTarget::FilterFrequency,
+        LfoTarget::FilterQ,
+        LfoTarget::Pan,
+        LfoTarget::Rate,
+        LfoTarget::Volume,
+    ];
+}
+
+impl LfoSettings {
+    pub fn shape(&self) -> LfoShape {
+        self.shape
+    }
+
+    /// Oscillations, or steps for stepped shapes, per second
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Amount the target param moves either side of its value, in the param's own units
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+
+    pub fn channel(&self) -> usize {
+        self.channel
+    }
+
+    pub fn target(&self) -> LfoTarget {
+        self.target
+    }
+}
+
 impl Settings {
     pub fn new() -> Result<Self, Error> {
         let settings_file = env::var("SETTINGS_FILE").unwrap_or("settings.yml".into());
@@ -68,6 +494,11 @@ impl Settings {
             .collect()
     }
 
+    /// File extensions, without the dot, of the files in sample dirs that are loaded as samples
+    pub fn sample_extensions(&self) -> &[String] {
+        &self.sample_extensions
+    }
+
     pub fn midi_channel(&self) -> midi_control::Channel {
         let channel_index = self.midi_channel - 1;
         channel_index.into()
@@ -77,10 +508,50 @@ impl Settings {
         &self.midi_device
     }
 
+    /// Tempo that synced loops are stretched to
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    /// Playback rate range that midi values are mapped onto for a channel. Negative values play
+    /// the sample in reverse.
+    pub fn rate_range(&self, channel_index: usize) -> Option<(f32, f32)> {
+        let channel = self.channels.get(channel_index)?;
+        Some((channel.rate_min, channel.rate_max))
+    }
+
+    pub fn channels(&self) -> &[ChannelSettings] {
+        &self.channels
+    }
+
     pub fn channel_count(&self) -> usize {
         self.channels.len()
     }
 
+    pub fn audio(&self) -> &AudioSettings {
+        &self.audio
+    }
+
+    pub fn cache(&self) -> &CacheSettings {
+        &self.cache
+    }
+
+    pub fn effects(&self) -> &EffectsSettings {
+        &self.effects
+    }
+
+    pub fn lfos(&self) -> &[LfoSettings] {
+        &self.lfos
+    }
+
+    pub fn limiter(&self) -> &LimiterSettings {
+        &self.limiter
+    }
+
+    pub fn recorder(&self) -> &RecorderSettings {
+        &self.recorder
+    }
+
     pub fn midi_settings(&self) -> Vec<&MidiSettings> {
         self.midi.iter().collect()
     }
@@ -97,6 +568,18 @@ impl Settings {
         Some((setting.channel.into(), &setting.param))
     }
 
+    /// Channel a midi note triggers, for channels in one shot mode
+    pub fn channel_from_trigger_note(&self, note: u8) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.trigger_note == Some(note))
+    }
+
+    pub fn lfo_index_from_midi_event(&self, cc_id: u8) -> Option<usize> {
+        let setting = self.midi.iter().find(|setting| setting.cc_id == cc_id)?;
+        (setting.lfo < self.lfos.len()).then_some(setting.lfo)
+    }
+
     pub fn validate(self) -> Result<Self, Error> {
         let cc_ids = &self
             .midi_settings()
@@ -108,14 +591,218 @@ impl Settings {
             return Err(Error::InvalidSettings("duplicate cc_ids".into()));
         }
 
+        let trigger_notes = self
+            .channels
+            .iter()
+            .filter_map(|channel| channel.trigger_note);
+        if has_dups(trigger_notes) {
+            return Err(Error::InvalidSettings("duplicate trigger_notes".into()));
+        }
+
+        if self.sample_extensions.is_empty() {
+            return Err(Error::InvalidSettings(
+                "sample_extensions must not be empty".into(),
+            ));
+        }
+
+        if self
+            .sample_extensions
+            .iter()
+            .any(|extension| extension.is_empty() || extension.starts_with('.'))
+        {
+            return Err(Error::InvalidSettings(
+                "sample_extensions must be given without the leading dot".into(),
+            ));
+        }
+
         if self.channel_count() > MAX_CHANNEL_COUNT {
             return Err(Error::InvalidSettings("too many channels".into()));
         }
 
+        if self
+            .channels
+            .iter()
+            .any(|channel| channel.rate_min >= channel.rate_max)
+        {
+            return Err(Error::InvalidSettings(
+                "rate_min must be less than rate_max".into(),
+            ));
+        }
+
+        if self
+            .channels
+            .iter()
+            .any(|channel| !(-1.0..=1.0).contains(&channel.pan))
+        {
+            return Err(Error::InvalidSettings(
+                "pan must be between -1.0 and 1.0".into(),
+            ));
+        }
+
+        if !(0.0..=MAX_DELAY_TIME).contains(&self.effects.delay_time) {
+            return Err(Error::InvalidSettings(format!(
+                "delay_time must be between 0 and {} seconds",
+                MAX_DELAY_TIME
+            )));
+        }
+
+        if !(0.0..1.0).contains(&self.effects.delay_feedback) {
+            return Err(Error::InvalidSettings(
+                "delay_feedback must be at least 0.0 and less than 1.0".into(),
+            ));
+        }
+
+        let grain_param_on_looper = self.midi.iter().any(|setting| {
+            setting.param.is_grain_param()
+                && self
+                    .channels
+                    .get(setting.channel as usize)
+                    .is_some_and(|channel| channel.engine != ChannelEngine::Granular)
+        });
+        if grain_param_on_looper {
+            return Err(Error::InvalidSettings(
+                "grain params can only be used on granular channels".into(),
+            ));
+        }
+
+        if self
+            .lfos
+            .iter()
+            .any(|lfo| lfo.channel >= self.channel_count())
+        {
+            return Err(Error::InvalidSettings("lfo channel does not exist".into()));
+        }
+
+        if self.lfos.iter().any(|lfo| lfo.rate <= 0.0) {
+            return Err(Error::InvalidSettings(
+                "lfo rate must be greater than zero".into(),
+            ));
+        }
+
+        if self
+            .audio
+            .sample_rate
+            .is_some_and(|sample_rate| !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate))
+        {
+            return Err(Error::InvalidSettings(format!(
+                "audio sample_rate must be between {} and {}",
+                MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+            )));
+        }
+
+        if let LatencyHint::Seconds(latency) = self.audio.latency_hint {
+            if latency <= 0.0 {
+                return Err(Error::InvalidSettings(
+                    "audio latency_hint must be above 0 seconds".into(),
+                ));
+            }
+        }
+
+        if !(-100.0..=0.0).contains(&self.limiter.threshold) {
+            return Err(Error::InvalidSettings(
+                "limiter threshold must be between -100 and 0 dB".into(),
+            ));
+        }
+
+        if self.limiter.ceiling > 0.0 {
+            return Err(Error::InvalidSettings(
+                "limiter ceiling must not be above 0 dB".into(),
+            ));
+        }
+
+        if self.cache.max_size_mb == 0 {
+            return Err(Error::InvalidSettings(
+                "cache max_size_mb must be greater than zero".into(),
+            ));
+        }
+
+        if self.cache.stream_above_mb == 0 {
+            return Err(Error::InvalidSettings(
+                "cache stream_above_mb must be greater than zero".into(),
+            ));
+        }
+
+        if !(1..=MAX_RECORDER_FILE_SIZE_MB).contains(&self.recorder.max_file_size_mb) {
+            return Err(Error::InvalidSettings(format!(
+                "recorder max_file_size_mb must be between 1 and {}",
+                MAX_RECORDER_FILE_SIZE_MB
+            )));
+        }
+
+        if !(MIN_BPM..=MAX_BPM).contains(&self.bpm) {
+            return Err(Error::InvalidSettings(format!(
+                "bpm must be between {} and {}",
+                MIN_BPM, MAX_BPM
+            )));
+        }
+
+        if self
+            .channels
+            .iter()
+            .any(|channel| channel.sync_beats.is_some_and(|beats| beats <= 0.0))
+        {
+            return Err(Error::InvalidSettings(
+                "sync_beats must be greater than zero".into(),
+            ));
+        }
+
+        if self.channels.iter().any(|channel| {
+            channel.sync_beats.is_some() && channel.engine == ChannelEngine::Granular
+        }) {
+            return Err(Error::InvalidSettings(
+                "sync_beats can only be used on looper channels".into(),
+            ));
+        }
+
+        if self
+            .channels
+            .iter()
+            .any(|channel| channel.pitch.abs() > MAX_PITCH_SHIFT)
+        {
+            return Err(Error::InvalidSettings(format!(
+                "pitch must be within {} semitones",
+                MAX_PITCH_SHIFT
+            )));
+        }
+
+        if self
+            .channels
+            .iter()
+            .any(|channel| channel.crossfade_time < 0.0)
+        {
+            return Err(Error::InvalidSettings(
+                "crossfade_time must not be negative".into(),
+            ));
+        }
+
         Ok(self)
     }
 }
 
+fn default_rate_min() -> f32 {
+    0.0
+}
+
+fn default_rate_max() -> f32 {
+    1.0
+}
+
+fn default_bpm() -> f32 {
+    120.0
+}
+
+fn default_crossfade_time() -> f64 {
+    0.25
+}
+
+fn default_sample_extensions() -> Vec<String> {
+    ["wav", "flac", "ogg", "mp3"].map(String::from).to_vec()
+}
+
+fn default_stream_above_mb() -> u64 {
+    64
+}
+
 fn has_dups<T>(iter: T) -> bool
 where
     T: IntoIterator,
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use web_audio_api::context::AudioContext;
+use web_audio_api::media::MediaElement;
+use web_audio_api::node::{AudioNode, MediaElementAudioSourceNode};
+
+use crate::audio_graph::Error;
+
+/// Slowest rate a stream plays at, the media element can't read from disk at rates near zero so
+/// the stream is paused below it instead
+const MIN_STREAM_RATE: f32 = 0.25;
+
+/// Plays a sample file straight from disk, reading ahead of the playhead and looping at the end of
+/// the file, for samples too long to decode into memory
+pub struct StreamingSource {
+    element: MediaElement,
+    node: MediaElementAudioSourceNode,
+    /// Set by `play` until `stop`
+    playing: bool,
+    /// Set while the rate is below the slowest the stream plays at
+    stalled: bool,
+}
+
+impl StreamingSource {
+    /// Open the file and fill the read ahead buffer, the stream is paused until `play`
+    pub fn new(context: &AudioContext, path: &Path, rate: f32) -> Result<Self, Error> {
+        let mut element =
+            MediaElement::new(path).map_err(|err| Error::StreamAudio(err.to_string()))?;
+        element.set_loop(true);
+        let node = context.create_media_element_source(&mut element);
+
+        let mut stream = Self {
+            element,
+            node,
+            playing: false,
+            stalled: false,
+        };
+        stream.set_rate(rate);
+        Ok(stream)
+    }
+
+    pub fn node(&self) -> &MediaElementAudioSourceNode {
+        &self.node
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+        self.update_paused();
+    }
+
+    /// Streams only play forwards, so negative rates play at the same speed forwards. Rates too
+    /// close to zero pause the stream until the rate is raised again.
+    pub fn set_rate(&mut self, rate: f32) {
+        let rate = rate.abs();
+        self.stalled = rate < MIN_STREAM_RATE;
+        if !self.stalled {
+            self.element.set_playback_rate(rate as f64);
+        }
+        self.update_paused();
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.update_paused();
+        self.node.disconnect();
+    }
+
+    fn update_paused(&self) {
+        if self.playing && !self.stalled {
+            self.element.play();
+        } else {
+            self.element.pause();
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use web_audio_api::AudioBuffer;
+
+use crate::message::ControlMessage;
+
+/// Samples in each overlapping frame
+const FRAME_LENGTH: usize = 2048;
+/// Frames overlap by half, where the window sums to a constant
+const SYNTHESIS_HOP: usize = FRAME_LENGTH / 2;
+/// How far either side of its nominal position a frame can move to line up with the last one
+const SEEK_RANGE: usize = 256;
+/// Only every nth sample is compared when lining frames up, which is plenty to find the waveform
+const SEEK_STRIDE: usize = 4;
+const MIN_STRETCH: f64 = 0.1;
+const MAX_STRETCH: f64 = 10.0;
+
+struct StretchJob {
+    buffer: AudioBuffer,
+    start: f64,
+    end: f64,
+    factor: f64,
+    generation: u64,
+}
+
+/// Stretches buffers on its own thread so the control thread isn't held up while wsola runs.
+/// `StretchFinished` is sent once a stretched buffer is ready to be taken.
+pub struct Stretcher {
+    jobs: Sender<StretchJob>,
+    results: Receiver<(u64, AudioBuffer)>,
+}
+
+impl Stretcher {
+    pub fn new(control_tx: Sender<ControlMessage>) -> Self {
+        let (jobs, job_rx) = channel::<StretchJob>();
+        let (result_tx, results) = channel();
+
+        // the thread ends once the stretcher is dropped
+        thread::spawn(move || {
+            while let Ok(job) = job_rx.recv() {
+                // only the latest job matters when several have queued up
+                let job = job_rx.try_iter().last().unwrap_or(job);
+                let stretched = time_stretch(&job.buffer, job.start, job.end, job.factor);
+                if result_tx.send((job.generation, stretched)).is_err() {
+                    break;
+                }
+                let _ = control_tx.send(ControlMessage::StretchFinished);
+            }
+        });
+
+        Self { jobs, results }
+    }
+
+    /// Queue `time_stretch` on the stretcher's thread, tagged with a generation to take the
+    /// result by
+    pub fn stretch(
+        &self,
+        buffer: &AudioBuffer,
+        start: f64,
+        end: f64,
+        factor: f64,
+        generation: u64,
+    ) {
+        let _ = self.jobs.send(StretchJob {
+            buffer: buffer.clone(),
+            start,
+            end,
+            factor,
+            generation,
+        });
+    }
+
+    /// The stretched buffer for a generation if it has finished, results of earlier
+    /// generations are thrown away
+    pub fn take(&self, generation: u64) -> Option<AudioBuffer> {
+        self.results
+            .try_iter()
+            .filter(|(result_generation, _)| *result_generation == generation)
+            .last()
+            .map(|(_, buffer)| buffer)
+    }
+}
+
+/// Change the length of the part of a buffer between two fractions without changing its pitch,
+/// a factor of 2.0 makes it twice as long. Frames are overlapped with waveform similarity (wsola)
+/// so the joins between them line up.
+pub fn time_stretch(buffer: &AudioBuffer, start: f64, end: f64, factor: f64) -> AudioBuffer {
+    let factor = factor.clamp(MIN_STRETCH, MAX_STRETCH);
+    let length = buffer.length();
+    let start_frame = ((start * length as f64) as usize).min(length - 1);
+    let end_frame = ((end * length as f64) as usize).clamp(start_frame + 1, length);
+    let channels: Vec<&[f32]> = (0..buffer.number_of_channels())
+        .map(|channel| &buffer.get_channel_data(channel)[start_frame..end_frame])
+        .collect();
+    let input_length = end_frame - start_frame;
+    let output_length = ((input_length as f64 * factor) as usize).max(1);
+
+    let window = hann_window();
+    let mono = mix_down(&channels);
+    let mut output = vec![vec![0.0; output_length]; channels.len()];
+    let mut window_sum = vec![0.0; output_length];
+    // where the frame after the last one placed would naturally continue from
+    let mut continuation: Option<usize> = None;
+
+    for output_position in (0..output_length).step_by(SYNTHESIS_HOP) {
+        let nominal = (output_position as f64 / factor) as usize;
+        let input_position = match continuation {
+            Some(continuation) => best_match(&mono, nominal, continuation),
+            None => nominal,
+        };
+
+        for (index, weight) in window.iter().enumerate() {
+            let output_index = output_position + index;
+            if output_index >= output_length {
+                break;
+            }
+            // read past the end of the region by wrapping, the region is played as a loop
+            let input_index = (input_position + index) % input_length;
+            for (channel, samples) in channels.iter().enumerate() {
+                output[channel][output_index] += samples[input_index] * weight;
+            }
+            window_sum[output_index] += weight;
+        }
+
+        continuation = Some(input_position + SYNTHESIS_HOP);
+    }
+
+    for samples in output.iter_mut() {
+        for (sample, sum) in samples.iter_mut().zip(&window_sum) {
+            if *sum > f32::EPSILON {
+                *sample /= sum;
+            }
+        }
+    }
+
+    AudioBuffer::from(output, buffer.sample_rate())
+}
+
+/// Position near `nominal` whose waveform best matches the one starting at `continuation`
+fn best_match(mono: &[f32], nominal: usize, continuation: usize) -> usize {
+    let first = nominal.saturating_sub(SEEK_RANGE);
+    let last = nominal + SEEK_RANGE;
+    let length = mono.len();
+
+    (first..=last)
+        .map(|position| (position, correlation(mono, position, continuation)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(position, _)| position % length)
+        .unwrap_or(nominal)
+}
+
+fn correlation(mono: &[f32], candidate: usize, continuation: usize) -> f32 {
+    let length = mono.len();
+    (0..SYNTHESIS_HOP)
+        .step_by(SEEK_STRIDE)
+        .map(|index| mono[(candidate + index) % length] * mono[(continuation + index) % length])
+        .sum()
+}
+
+fn mix_down(channels: &[&[f32]]) -> Vec<f32> {
+    let length = channels
+        .first()
+        .map(|samples| samples.len())
+        .unwrap_or_default();
+    (0..length)
+        .map(|index| channels.iter().map(|samples| samples[index]).sum())
+        .collect()
+}
+
+/// Periodic hann window, overlapping copies half a frame apart sum to one
+fn hann_window() -> Vec<f32> {
+    (0..FRAME_LENGTH)
+        .map(|index| {
+            let position = index as f32 / FRAME_LENGTH as f32;
+            0.5 - 0.5 * (2.0 * std::f32::consts::PI * position).cos()
+        })
+        .collect()
+}
@@ -0,0 +1,130 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use web_audio_api::context::{AudioContextState, BaseAudioContext, ConcreteBaseAudioContext};
+
+use crate::message::ControlMessage;
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(500);
+/// Checks in a row the audio clock can stand still while running before the context is lost
+const MAX_STALLED_CHECKS: usize = 4;
+/// Time between asking for a rebuild and asking again if no new context has been handed over,
+/// doubled after each attempt up to the max
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct WatchdogState {
+    /// Set once the watched context is lost, until a context to replace it is handed over
+    lost: bool,
+    replacement: Option<ConcreteBaseAudioContext>,
+}
+
+/// Handle on the thread watching the audio context, for handing it the context of a rebuilt
+/// audio graph
+#[derive(Clone)]
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+impl Watchdog {
+    /// Watch the context of a rebuilt audio graph instead of the lost one
+    pub fn watch(&self, context: ConcreteBaseAudioContext) {
+        let mut state = self.state();
+        state.lost = false;
+        state.replacement = Some(context);
+    }
+
+    /// Whether the watched context has been lost and not yet replaced
+    pub fn is_lost(&self) -> bool {
+        self.state().lost
+    }
+
+    fn set_lost(&self) {
+        self.state().lost = true;
+    }
+
+    fn take_replacement(&self) -> Option<ConcreteBaseAudioContext> {
+        self.state().replacement.take()
+    }
+
+    fn state(&self) -> MutexGuard<'_, WatchdogState> {
+        self.state.lock().expect("Watchdog state lock")
+    }
+}
+
+/// Watch an audio context and ask for the audio graph to be rebuilt once it closes or its clock
+/// stops moving, which is how a lost output device shows up. Rebuilds are asked for again, less
+/// and less often, until the context of a rebuilt graph is handed over with `Watchdog::watch`,
+/// which is then watched in turn.
+pub fn spawn_watchdog(
+    context: ConcreteBaseAudioContext,
+    control_tx: Sender<ControlMessage>,
+) -> Watchdog {
+    let watchdog = Watchdog {
+        state: Arc::default(),
+    };
+    let handle = watchdog.clone();
+
+    thread::spawn(move || {
+        let mut context = context;
+
+        loop {
+            wait_until_lost(&context);
+            handle.set_lost();
+            println!("audio context lost, rebuilding audio graph");
+
+            match wait_for_replacement(&handle, &control_tx) {
+                Some(replacement) => context = replacement,
+                None => break,
+            }
+        }
+    });
+
+    watchdog
+}
+
+fn wait_until_lost(context: &ConcreteBaseAudioContext) {
+    let mut last_time = context.current_time();
+    let mut stalled_checks = 0;
+
+    loop {
+        thread::sleep(WATCHDOG_INTERVAL);
+
+        let time = context.current_time();
+        match context.state() {
+            AudioContextState::Closed => return,
+            AudioContextState::Running if time <= last_time => stalled_checks += 1,
+            _ => stalled_checks = 0,
+        }
+        last_time = time;
+
+        if stalled_checks >= MAX_STALLED_CHECKS {
+            return;
+        }
+    }
+}
+
+/// Ask for rebuilds until a new context is handed over, `None` once nothing is listening
+fn wait_for_replacement(
+    watchdog: &Watchdog,
+    control_tx: &Sender<ControlMessage>,
+) -> Option<ConcreteBaseAudioContext> {
+    let mut retry_interval = MIN_RETRY_INTERVAL;
+
+    loop {
+        control_tx.send(ControlMessage::RebuildAudioGraph).ok()?;
+        thread::sleep(retry_interval);
+
+        if let Some(replacement) = watchdog.take_replacement() {
+            return Some(replacement);
+        }
+        retry_interval = (retry_interval * 2).min(MAX_RETRY_INTERVAL);
+        println!(
+            "audio graph not rebuilt yet, trying again in {}s",
+            retry_interval.as_secs()
+        );
+    }
+}
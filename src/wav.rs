@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const RIFF_ID: &[u8; 4] = b"RIFF";
+const WAVE_ID: &[u8; 4] = b"WAVE";
+const FMT_CHUNK_ID: &[u8; 4] = b"fmt ";
+const SMPL_CHUNK_ID: &[u8; 4] = b"smpl";
+/// Tempo and root note written by loop editors
+const ACID_CHUNK_ID: &[u8; 4] = b"acid";
+/// Bytes before the first loop in a smpl chunk
+const SMPL_HEADER_LENGTH: usize = 36;
+const SMPL_LOOP_LENGTH: usize = 24;
+const ACID_CHUNK_LENGTH: usize = 24;
+/// Longest chunk that is read into memory, room for a smpl chunk with thousands of loops. Lengths
+/// come from the file, so a damaged one could otherwise ask for gigabytes.
+const MAX_CHUNK_LENGTH: usize = 64 * 1024;
+/// Set in the acid flags when the root note is meaningful
+const ACID_ROOT_NOTE_SET: u32 = 0x02;
+
+/// A chunk read from a RIFF wav file
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// Read the fmt, smpl and acid chunks from a wav file, seeking past the rest. A chunk cut short
+/// by the end of the file is ignored along with anything after it.
+pub fn read_chunks(path: &Path) -> io::Result<Vec<Chunk>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+
+    if &header[0..4] != RIFF_ID || &header[8..12] != WAVE_ID {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a wav file"));
+    }
+
+    let mut chunks = vec![];
+    let mut chunk_header = [0; 8];
+    while reader.read_exact(&mut chunk_header).is_ok() {
+        let id = [
+            chunk_header[0],
+            chunk_header[1],
+            chunk_header[2],
+            chunk_header[3],
+        ];
+        let length = read_u32(&chunk_header, 4) as usize;
+        // chunks are padded to an even number of bytes
+        let padded_length = length + length % 2;
+
+        let wanted = [FMT_CHUNK_ID, SMPL_CHUNK_ID, ACID_CHUNK_ID].contains(&&id);
+        if !wanted || padded_length > MAX_CHUNK_LENGTH {
+            reader.seek(SeekFrom::Current(padded_length as i64))?;
+            continue;
+        }
+
+        let mut data = vec![0; length];
+        if reader.read_exact(&mut data).is_err() {
+            break;
+        }
+        // the padding byte is left off the last chunk by some writers
+        reader.seek(SeekFrom::Current((padded_length - length) as i64))?;
+        chunks.push(Chunk { id, data });
+    }
+
+    Ok(chunks)
+}
+
+/// Loop start and end in seconds from the first loop in a wav file's smpl chunk
+pub fn read_loop_points(path: &Path) -> Option<(f64, f64)> {
+    let chunks = read_chunks(path).ok()?;
+    loop_points(&chunks)
+}
+
+pub fn loop_points(chunks: &[Chunk]) -> Option<(f64, f64)> {
+    let sample_rate = sample_rate(chunks)? as f64;
+    let smpl = find_chunk(chunks, SMPL_CHUNK_ID)?;
+
+    if smpl.data.len() < SMPL_HEADER_LENGTH + SMPL_LOOP_LENGTH || read_u32(&smpl.data, 28) == 0 {
+        return None;
+    }
+
+    let first_loop = &smpl.data[SMPL_HEADER_LENGTH..];
+    let start = read_u32(first_loop, 8) as f64;
+    // the end sample is played as part of the loop
+    let end = read_u32(first_loop, 12) as f64 + 1.0;
+
+    (end > start).then_some((start / sample_rate, end / sample_rate))
+}
+
+/// Tempo in beats per minute from a wav file's acid chunk
+pub fn acid_tempo(chunks: &[Chunk]) -> Option<f32> {
+    let acid = find_chunk(chunks, ACID_CHUNK_ID)?;
+    if acid.data.len() < ACID_CHUNK_LENGTH {
+        return None;
+    }
+
+    let tempo = f32::from_bits(read_u32(&acid.data, 20));
+    (tempo.is_finite() && tempo > 0.0).then_some(tempo)
+}
+
+/// Root note as a midi note number from a wav file's acid chunk
+pub fn acid_root_note(chunks: &[Chunk]) -> Option<u8> {
+    let acid = find_chunk(chunks, ACID_CHUNK_ID)?;
+    if acid.data.len() < ACID_CHUNK_LENGTH || read_u32(&acid.data, 0) & ACID_ROOT_NOTE_SET == 0 {
+        return None;
+    }
+
+    u8::try_from(read_u16(&acid.data, 4)).ok()
+}
+
+/// Midi note that plays the sample at its recorded pitch, from a wav file's smpl chunk
+pub fn smpl_unity_note(chunks: &[Chunk]) -> Option<u8> {
+    let smpl = find_chunk(chunks, SMPL_CHUNK_ID)?;
+    if smpl.data.len() < SMPL_HEADER_LENGTH {
+        return None;
+    }
+
+    u8::try_from(read_u32(&smpl.data, 12)).ok()
+}
+
+pub fn sample_rate(chunks: &[Chunk]) -> Option<u32> {
+    let fmt = find_chunk(chunks, FMT_CHUNK_ID)?;
+    (fmt.data.len() >= 8).then(|| read_u32(&fmt.data, 4))
+}
+
+pub fn find_chunk<'a>(chunks: &'a [Chunk], id: &[u8; 4]) -> Option<&'a Chunk> {
+    chunks.iter().find(|chunk| &chunk.id == id)
+}
+
+pub fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fmt_chunk(sample_rate: u32) -> Chunk {
+        let mut data = vec![0; 16];
+        data[4..8].copy_from_slice(&sample_rate.to_le_bytes());
+        Chunk {
+            id: *FMT_CHUNK_ID,
+            data,
+        }
+    }
+
+    fn smpl_chunk(unity_note: u32, loops: &[(u32, u32)]) -> Chunk {
+        let mut data = vec![0; SMPL_HEADER_LENGTH];
+        data[12..16].copy_from_slice(&unity_note.to_le_bytes());
+        data[28..32].copy_from_slice(&(loops.len() as u32).to_le_bytes());
+        for (start, end) in loops {
+            let mut sample_loop = [0; SMPL_LOOP_LENGTH];
+            sample_loop[8..12].copy_from_slice(&start.to_le_bytes());
+            sample_loop[12..16].copy_from_slice(&end.to_le_bytes());
+            data.extend_from_slice(&sample_loop);
+        }
+        Chunk {
+            id: *SMPL_CHUNK_ID,
+            data,
+        }
+    }
+
+    fn acid_chunk(flags: u32, root_note: u16, tempo: f32) -> Chunk {
+        let mut data = vec![0; ACID_CHUNK_LENGTH];
+        data[0..4].copy_from_slice(&flags.to_le_bytes());
+        data[4..6].copy_from_slice(&root_note.to_le_bytes());
+        data[20..24].copy_from_slice(&tempo.to_bits().to_le_bytes());
+        Chunk {
+            id: *ACID_CHUNK_ID,
+            data,
+        }
+    }
+
+    /// A wav file in the temp dir made of `chunks`, each given as id, declared length and data
+    fn write_wav(name: &str, chunks: &[(&[u8; 4], u32, Vec<u8>)]) -> PathBuf {
+        let mut bytes = RIFF_ID.to_vec();
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+        bytes.extend_from_slice(WAVE_ID);
+        for (id, length, data) in chunks {
+            bytes.extend_from_slice(*id);
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        let path = std::env::temp_dir().join(format!("biome-wav-test-{}.wav", name));
+        fs::write(&path, bytes).expect("Test wav written");
+        path
+    }
+
+    #[test]
+    fn loop_points_from_first_smpl_loop() {
+        let chunks = [fmt_chunk(48000), smpl_chunk(60, &[(24000, 47999), (0, 10)])];
+        assert_eq!(loop_points(&chunks), Some((0.5, 1.0)));
+    }
+
+    #[test]
+    fn loop_points_need_a_loop_and_a_sample_rate() {
+        assert_eq!(loop_points(&[fmt_chunk(48000), smpl_chunk(60, &[])]), None);
+        assert_eq!(loop_points(&[smpl_chunk(60, &[(0, 100)])]), None);
+    }
+
+    #[test]
+    fn acid_tempo_and_root_note() {
+        let chunks = [acid_chunk(ACID_ROOT_NOTE_SET, 57, 128.0)];
+        assert_eq!(acid_tempo(&chunks), Some(128.0));
+        assert_eq!(acid_root_note(&chunks), Some(57));
+    }
+
+    #[test]
+    fn acid_root_note_only_when_flagged() {
+        let chunks = [acid_chunk(0, 57, 0.0)];
+        assert_eq!(acid_root_note(&chunks), None);
+        assert_eq!(acid_tempo(&chunks), None);
+    }
+
+    #[test]
+    fn smpl_unity_note_fits_a_midi_note() {
+        assert_eq!(smpl_unity_note(&[smpl_chunk(62, &[])]), Some(62));
+        assert_eq!(smpl_unity_note(&[smpl_chunk(300, &[])]), None);
+    }
+
+    #[test]
+    fn read_chunks_skips_unwanted_and_padded_chunks() {
+        let path = write_wav(
+            "padded",
+            &[
+                (b"LIST", 3, vec![1, 2, 3, 0]),
+                (FMT_CHUNK_ID, 16, fmt_chunk(44100).data),
+                (b"data", 4, vec![0; 4]),
+            ],
+        );
+        let chunks = read_chunks(&path).expect("Chunks read");
+        fs::remove_file(&path).ok();
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(sample_rate(&chunks), Some(44100));
+    }
+
+    #[test]
+    fn read_chunks_ignores_a_truncated_chunk() {
+        let path = write_wav(
+            "truncated",
+            &[
+                (FMT_CHUNK_ID, 16, fmt_chunk(48000).data),
+                (SMPL_CHUNK_ID, 60, vec![0; 8]),
+            ],
+        );
+        let chunks = read_chunks(&path).expect("Chunks read");
+        fs::remove_file(&path).ok();
+
+        assert_eq!(chunks.len(), 1);
+        assert!(find_chunk(&chunks, SMPL_CHUNK_ID).is_none());
+    }
+
+    #[test]
+    fn read_chunks_seeks_past_oversized_chunks() {
+        let length = MAX_CHUNK_LENGTH as u32 + 2;
+        let path = write_wav(
+            "oversized",
+            &[
+                (SMPL_CHUNK_ID, length, vec![0; length as usize]),
+                (FMT_CHUNK_ID, 16, fmt_chunk(48000).data),
+            ],
+        );
+        let chunks = read_chunks(&path).expect("Chunks read");
+        fs::remove_file(&path).ok();
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(sample_rate(&chunks), Some(48000));
+    }
+}